@@ -0,0 +1,335 @@
+//! Audit trail of the mutations of [`MetaInfo`].
+//!
+//! The audit mode is enabled with [`MetaInfo::enable_audit`], usually on the root `MetaInfo` of a
+//! request. The journal is shared by the `MetaInfo`s derived from it afterwards, and keeps the
+//! last mutations with their call-site locations, so it can be dumped with
+//! [`MetaInfo::audit_journal`] when something goes wrong.
+//!
+//! Like observers, `clear` is not audited. The entries set through the setters, the guards,
+//! `extend` and the propagators are located at the call site in the user code.
+//!
+//! Examples:
+//! ```rust
+//! use metainfo::{Forward, MetaInfo};
+//!
+//! let mut mi = MetaInfo::new();
+//! mi.enable_audit(64);
+//! mi.set_persistent("TENANT", "t1");
+//!
+//! let (_, mut child) = mi.derive();
+//! child.set_persistent("TENANT", "t2");
+//!
+//! let journal = child.audit_journal().unwrap();
+//! assert_eq!(journal.entries().len(), 2);
+//! assert!(journal.entries().iter().all(|e| e.key == "TENANT"));
+//! println!("{journal}");
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    panic::Location,
+    sync::{Arc, Mutex},
+};
+
+use faststr::FastStr;
+
+use crate::{Backward, Forward, MetaInfo, MutationKind, Section};
+
+/// A mutation recorded in the audit journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub kind: MutationKind,
+    pub section: Section,
+    /// The key of the mutated entry, named like [`Mutation::key`](crate::Mutation::key).
+    pub key: FastStr,
+    /// Whether the entry was present in the current scope before the mutation.
+    pub old: bool,
+    /// Whether the entry is present in the current scope after the mutation, which is the same
+    /// as `old` if the mutation is vetoed by an [`Observer`](crate::Observer).
+    pub new: bool,
+    /// The call site of the mutation.
+    pub location: &'static Location<'static>,
+    /// The number of parents of the mutated `MetaInfo`.
+    pub depth: usize,
+}
+
+/// A snapshot of the audit journal, returned by [`MetaInfo::audit_journal`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditJournal {
+    entries: Vec<AuditEntry>,
+    dropped: u64,
+}
+
+impl AuditJournal {
+    /// Get the recorded mutations, from the oldest to the latest.
+    #[inline]
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    /// Get the number of mutations dropped because the journal was full.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// One line per entry, e.g. `src/main.rs:10:5 [1] Insert persistent TENANT: present -> present`.
+impl fmt::Display for AuditJournal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let presence = |present: bool| if present { "present" } else { "absent" };
+        if self.dropped > 0 {
+            writeln!(f, "... {} dropped", self.dropped)?;
+        }
+        for (i, e) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(
+                f,
+                "{} [{}] {:?} {} {}: {} -> {}",
+                e.location,
+                e.depth,
+                e.kind,
+                e.section,
+                e.key,
+                presence(e.old),
+                presence(e.new)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Journal {
+    entries: VecDeque<AuditEntry>,
+    capacity: usize,
+    dropped: u64,
+}
+
+pub(crate) type Audit = Arc<Mutex<Journal>>;
+
+impl MetaInfo {
+    /// Enable the audit trail on this `MetaInfo` and the `MetaInfo`s derived from it afterwards,
+    /// keeping the last `capacity` mutations.
+    ///
+    /// Does nothing if the audit trail is already enabled.
+    pub fn enable_audit(&mut self, capacity: usize) {
+        self.audit.get_or_insert_with(|| {
+            Arc::new(Mutex::new(Journal {
+                entries: VecDeque::with_capacity(capacity.min(1024)),
+                capacity,
+                dropped: 0,
+            }))
+        });
+    }
+
+    /// Get a snapshot of the audit journal, `None` if the audit trail is not enabled.
+    pub fn audit_journal(&self) -> Option<AuditJournal> {
+        let journal = self
+            .audit
+            .as_ref()?
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        Some(AuditJournal {
+            entries: journal.entries.iter().cloned().collect(),
+            dropped: journal.dropped,
+        })
+    }
+
+    /// Check if the entry is present in the current scope.
+    fn contains_in_scope(&self, section: Section, key: &str) -> bool {
+        match section {
+            Section::Typed => self
+                .tmap
+                .as_ref()
+                .is_some_and(|tmap| tmap.type_names().any(|name| name == key)),
+            Section::FastStr => self
+                .faststr_tmap
+                .as_ref()
+                .is_some_and(|faststr_tmap| faststr_tmap.type_names().any(|name| name == key)),
+            Section::String => self
+                .smap
+                .as_ref()
+                .is_some_and(|smap| smap.contains_key(key)),
+            Section::Persistent => self.get_persistent(key).is_some(),
+            Section::Transient => self.get_transient(key).is_some(),
+            Section::Upstream => self.get_upstream(key).is_some(),
+            Section::BackwardTransient => self.get_backward_transient(key).is_some(),
+            Section::BackwardDownstream => self.get_backward_downstream(key).is_some(),
+        }
+    }
+
+    /// Record a mutation about to be applied at `location` if the audit trail is enabled.
+    #[inline]
+    pub(crate) fn audit(
+        &self,
+        kind: MutationKind,
+        section: Section,
+        key: &str,
+        allowed: bool,
+        location: &'static Location<'static>,
+    ) {
+        let Some(audit) = self.audit.as_ref() else {
+            return;
+        };
+        let old = self.contains_in_scope(section, key);
+        let new = match (allowed, kind) {
+            (false, _) => old,
+            (true, MutationKind::Insert) => true,
+            (true, MutationKind::Remove) => false,
+        };
+        let mut depth = 0;
+        let mut cur = self.parent.as_deref();
+        while let Some(parent) = cur {
+            depth += 1;
+            cur = parent.parent.as_deref();
+        }
+        let entry = AuditEntry {
+            kind,
+            section,
+            key: FastStr::new(key),
+            old,
+            new,
+            location,
+            depth,
+        };
+
+        let mut journal = audit.lock().unwrap_or_else(|e| e.into_inner());
+        if journal.capacity == 0 {
+            journal.dropped += 1;
+            return;
+        }
+        if journal.entries.len() == journal.capacity {
+            journal.entries.pop_front();
+            journal.dropped += 1;
+        }
+        journal.entries.push_back(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{type_map::TypeNames, Mutation};
+
+    #[test]
+    fn test_audit() {
+        let u8_name = TypeNames::name_of::<u8>();
+        let mut mi = MetaInfo::new();
+        assert!(mi.audit_journal().is_none());
+        mi.enable_audit(16);
+        mi.add_observer(|_: &MetaInfo, m: &Mutation<'_>| m.key != "LOCKED");
+
+        let line = line!() + 1;
+        mi.set_persistent("TENANT", "t1");
+        mi.insert(1u8);
+        mi.set_persistent("LOCKED", "v");
+        let (_, mut child) = mi.derive();
+        child.set_persistent("TENANT", "t2");
+        child.remove::<u8>();
+        child.del_backward_transient("B");
+
+        let journal = child.audit_journal().unwrap();
+        assert_eq!(journal.dropped(), 0);
+        let entries = journal.entries();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[0].location.file(), file!());
+        assert_eq!(entries[0].location.line(), line);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.key.as_str(), e.depth, e.old, e.new))
+                .collect::<Vec<_>>(),
+            [
+                ("TENANT", 0, false, true),
+                (u8_name.as_str(), 0, false, true),
+                ("LOCKED", 0, false, false),
+                ("TENANT", 1, true, true),
+                // typed entries of the parent are not in the current scope
+                (u8_name.as_str(), 1, false, false),
+                ("B", 1, false, false),
+            ]
+        );
+        assert!(journal
+            .to_string()
+            .lines()
+            .nth(3)
+            .unwrap()
+            .ends_with("[1] Insert persistent TENANT: present -> present"));
+    }
+
+    #[test]
+    fn test_locations() {
+        use std::time::Duration;
+
+        use crate::{HttpPropagator, Propagator, HOP_COUNT_KEY};
+
+        let mut mi = MetaInfo::new();
+        mi.enable_audit(64);
+        let start = line!();
+        mi.set_timeout(Duration::from_secs(1));
+        mi.remove_deadline();
+        mi.set_deadline_from_timeout_ms("1000", Duration::ZERO);
+        mi.start_hop_tracking();
+        mi.enter_service("a", 10).unwrap();
+        mi.import_persistent(HOP_COUNT_KEY.into(), "1");
+        mi.import_upstream("USER".into(), "u1");
+        mi.strip_rpc_prefix_and_set_persistent("RPC_PERSIST_TENANT", "t1");
+        mi.strip_http_prefix_and_set_upstream("rpc-transit-user", "u2");
+        mi.strip_http_prefix_and_set_backward_downstream("rpc-backward-cost", "1");
+        HttpPropagator.extract_into(
+            &[("rpc-persist-region".to_owned(), "r1".to_owned())]
+                .into_iter()
+                .collect::<std::collections::HashMap<_, _>>(),
+            &mut mi,
+        );
+        drop(mi.scoped_insert(1u8));
+        drop(mi.scoped_set_transient("KEY", "v"));
+        let mut other = MetaInfo::new();
+        other.set_persistent("OTHER", "o");
+        mi.extend(other);
+        let end = line!();
+
+        let journal = mi.audit_journal().unwrap();
+        assert_eq!(journal.entries().len(), 14);
+        for e in journal.entries() {
+            assert_eq!(e.location.file(), file!(), "{e:?}");
+            assert!((start..end).contains(&e.location.line()), "{e:?}");
+        }
+
+        #[cfg(feature = "task_local")]
+        {
+            let mut mi = MetaInfo::new();
+            mi.enable_audit(4);
+            let line = line!() + 2;
+            let journal = crate::METAINFO.sync_scope(std::cell::RefCell::new(mi), || {
+                drop(crate::scoped::insert_current(1u8));
+                crate::METAINFO.with(|mi| mi.borrow().audit_journal().unwrap())
+            });
+            assert_eq!(journal.entries()[0].location.line(), line);
+        }
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut mi = MetaInfo::new();
+        mi.enable_audit(2);
+        for i in 0..5 {
+            mi.insert_string(FastStr::new(i.to_string()), "v".into());
+        }
+        let journal = mi.audit_journal().unwrap();
+        assert_eq!(journal.dropped(), 3);
+        assert_eq!(
+            journal
+                .entries()
+                .iter()
+                .map(|e| e.key.as_str())
+                .collect::<Vec<_>>(),
+            ["3", "4"]
+        );
+        assert!(journal.to_string().starts_with("... 3 dropped\n"));
+    }
+}
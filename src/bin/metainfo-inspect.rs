@@ -0,0 +1,455 @@
+//! Inspect the metainfo carried by a request.
+//!
+//! Reads HTTP header dumps (`Name: value`) or RPC-style key/value lines (`KEY=value`) from a file
+//! or stdin, imports them like a server would, and prints the resulting sections along with the
+//! lines which are rejected or lost.
+//!
+//! ```text
+//! Usage: metainfo-inspect [--scheme auto|rpc|http|escaped] [--json] [FILE]
+//! ```
+//!
+//! The tool is built with the `json` feature, e.g.
+//! `cargo run --features json --bin metainfo-inspect -- --json`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fmt, fs,
+    io::{self, Read},
+    process::ExitCode,
+};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+use metainfo::{
+    Backward, EscapedHttpPropagator, Forward, HttpPropagator, MetaInfo, Propagator, RpcPropagator,
+    DEADLINE_TIMEOUT_KEY,
+};
+use serde::Serialize;
+
+const USAGE: &str = "Usage: metainfo-inspect [--scheme auto|rpc|http|escaped] [--json] [FILE]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    Rpc,
+    Http,
+    Escaped,
+}
+
+impl Scheme {
+    /// Check if an exported key is the same as the key it's imported from, http header names
+    /// are case insensitive.
+    fn same_key(&self, exported: &str, key: &str) -> bool {
+        match self {
+            Scheme::Rpc => exported == key,
+            Scheme::Http | Scheme::Escaped => exported.eq_ignore_ascii_case(key),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scheme::Rpc => "rpc",
+            Scheme::Http => "http",
+            Scheme::Escaped => "escaped",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Section {
+    Persistent,
+    Transient,
+    Backward,
+}
+
+impl Section {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Section::Persistent => "persistent",
+            Section::Transient => "transient",
+            Section::Backward => "backward",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum IssueKind {
+    /// The key has no prefix of the scheme.
+    Rejected,
+    /// The value is invalid, e.g. a non-numeric timeout.
+    Invalid,
+    /// The key is not re-exported with the same name.
+    Lossy,
+    /// The key is set again by a later line.
+    Overwritten,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Issue {
+    line: usize,
+    key: String,
+    kind: IssueKind,
+    detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    persistent: BTreeMap<String, String>,
+    transient: BTreeMap<String, String>,
+    backward: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_ms: Option<String>,
+    issues: Vec<Issue>,
+}
+
+/// Split a line into a key and a value, skipping blank lines, comments and HTTP start lines.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("HTTP/") {
+        return None;
+    }
+    let sep = line.find([':', '='])?;
+    let (key, value) = (line[..sep].trim(), line[sep + 1..].trim());
+    if key.is_empty() || key.contains(' ') {
+        // e.g. `GET /path HTTP/1.1`
+        return None;
+    }
+    Some((key, value))
+}
+
+/// The section, stripped key and value of an imported entry.
+enum Imported {
+    Entry(Section, FastStr, FastStr),
+    Timeout,
+    InvalidTimeout,
+}
+
+fn import_with<P: Propagator>(propagator: &P, key: &str, value: &str) -> Option<Imported> {
+    let mut probe = MetaInfo::new();
+    let value = FastStr::new(value);
+    // the value is read back, since it may be changed on import, e.g. the hop count
+    let first = |map: Option<&AHashMap<FastStr, FastStr>>| {
+        map.and_then(|m| m.iter().next().map(|(k, v)| (k.clone(), v.clone())))
+    };
+    if propagator.extract_persistent(&mut probe, key, value.clone()) {
+        return first(probe.get_all_persistents())
+            .map(|(k, v)| Imported::Entry(Section::Persistent, k, v));
+    }
+    if propagator.extract_upstream(&mut probe, key, value.clone()) {
+        return Some(match first(probe.get_all_upstreams()) {
+            // not a number of milliseconds, kept as an upstream
+            Some((k, _)) if k == DEADLINE_TIMEOUT_KEY => Imported::InvalidTimeout,
+            Some((k, v)) => Imported::Entry(Section::Transient, k, v),
+            None => Imported::Timeout,
+        });
+    }
+    if propagator.extract_backward_downstream(&mut probe, key, value) {
+        return first(probe.get_all_backward_downstreams())
+            .map(|(k, v)| Imported::Entry(Section::Backward, k, v));
+    }
+    None
+}
+
+fn import(scheme: Scheme, key: &str, value: &str) -> Option<Imported> {
+    match scheme {
+        Scheme::Rpc => import_with(&RpcPropagator, key, value),
+        Scheme::Http => import_with(&HttpPropagator, key, value),
+        Scheme::Escaped => import_with(&EscapedHttpPropagator, key, value),
+    }
+}
+
+/// Get the name under which an imported key is exported again.
+fn export_with<P: Propagator>(propagator: &P, section: Section, key: &FastStr) -> Option<FastStr> {
+    let mut mi = MetaInfo::new();
+    let mut carrier = AHashMap::new();
+    match section {
+        Section::Persistent => mi.set_persistent(key.clone(), ""),
+        Section::Transient => mi.set_transient(key.clone(), ""),
+        Section::Backward => mi.set_backward_transient(key.clone(), ""),
+    }
+    match section {
+        Section::Backward => propagator.inject_backward(&mi, &mut carrier),
+        _ => propagator.inject(&mi, &mut carrier),
+    }
+    carrier.into_keys().next()
+}
+
+fn export(scheme: Scheme, section: Section, key: &FastStr) -> Option<FastStr> {
+    match scheme {
+        Scheme::Rpc => export_with(&RpcPropagator, section, key),
+        Scheme::Http => export_with(&HttpPropagator, section, key),
+        Scheme::Escaped => export_with(&EscapedHttpPropagator, section, key),
+    }
+}
+
+fn inspect(input: &str, schemes: &[Scheme]) -> Report {
+    let mut report = Report::default();
+    // the line which set each key, to report the overwritten ones
+    let mut set_by: HashMap<(Section, FastStr), (usize, String)> = HashMap::new();
+
+    for (i, line) in input.lines().enumerate() {
+        let line_no = i + 1;
+        let Some((key, value)) = parse_line(line) else {
+            continue;
+        };
+        let imported = schemes
+            .iter()
+            .find_map(|&scheme| import(scheme, key, value).map(|imported| (scheme, imported)));
+        let (scheme, imported) = match imported {
+            Some(imported) => imported,
+            None => {
+                let names: Vec<_> = schemes.iter().map(Scheme::to_string).collect();
+                report.issues.push(Issue {
+                    line: line_no,
+                    key: key.to_string(),
+                    kind: IssueKind::Rejected,
+                    detail: format!("no {} prefix", names.join(" or ")),
+                });
+                continue;
+            }
+        };
+        let (stripped, imported_value) = match imported {
+            Imported::Entry(section, stripped, value) => ((section, stripped), value),
+            Imported::Timeout => {
+                report.timeout_ms = Some(value.to_string());
+                continue;
+            }
+            Imported::InvalidTimeout => {
+                report.issues.push(Issue {
+                    line: line_no,
+                    key: key.to_string(),
+                    kind: IssueKind::Invalid,
+                    detail: format!("{DEADLINE_TIMEOUT_KEY} is not a number of milliseconds"),
+                });
+                continue;
+            }
+        };
+
+        let (section, name) = &stripped;
+        if let Some(exported) = export(scheme, *section, name) {
+            if !scheme.same_key(&exported, key) {
+                report.issues.push(Issue {
+                    line: line_no,
+                    key: key.to_string(),
+                    kind: IssueKind::Lossy,
+                    detail: format!("imported as {name}, re-exported as {exported}"),
+                });
+            }
+        }
+        let map = match section {
+            Section::Persistent => &mut report.persistent,
+            Section::Transient => &mut report.transient,
+            Section::Backward => &mut report.backward,
+        };
+        map.insert(name.to_string(), imported_value.to_string());
+        if let Some((prev_line, prev_key)) = set_by.insert(stripped.clone(), (line_no, key.into()))
+        {
+            report.issues.push(Issue {
+                line: prev_line,
+                key: prev_key,
+                kind: IssueKind::Overwritten,
+                detail: format!(
+                    "{} {} is set again by line {line_no}",
+                    section.as_str(),
+                    name
+                ),
+            });
+        }
+    }
+
+    report.issues.sort_by_key(|issue| issue.line);
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (section, map) in [
+            (Section::Persistent, &self.persistent),
+            (Section::Transient, &self.transient),
+            (Section::Backward, &self.backward),
+        ] {
+            writeln!(f, "{}:", section.as_str())?;
+            for (k, v) in map {
+                writeln!(f, "  {k} = {v}")?;
+            }
+        }
+        if let Some(timeout) = &self.timeout_ms {
+            writeln!(f, "timeout: {timeout}ms")?;
+        }
+        if !self.issues.is_empty() {
+            writeln!(f, "issues:")?;
+            for issue in &self.issues {
+                let kind = match issue.kind {
+                    IssueKind::Rejected => "rejected",
+                    IssueKind::Invalid => "invalid",
+                    IssueKind::Lossy => "lossy",
+                    IssueKind::Overwritten => "overwritten",
+                };
+                writeln!(
+                    f,
+                    "  line {}: {}: {kind}, {}",
+                    issue.line, issue.key, issue.detail
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Args {
+    schemes: Vec<Scheme>,
+    json: bool,
+    file: Option<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        schemes: vec![Scheme::Rpc, Scheme::Http],
+        json: false,
+        file: None,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => parsed.json = true,
+            "--scheme" => {
+                parsed.schemes = match args.next().as_deref() {
+                    Some("auto") => vec![Scheme::Rpc, Scheme::Http],
+                    Some("rpc") => vec![Scheme::Rpc],
+                    Some("http") => vec![Scheme::Http],
+                    Some("escaped") => vec![Scheme::Escaped],
+                    _ => return Err("--scheme expects auto, rpc, http or escaped".into()),
+                }
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option {arg}")),
+            _ if parsed.file.is_none() => parsed.file = Some(arg),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+    Ok(parsed)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("error: {err}");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let input = match args.file.as_deref() {
+        None | Some("-") => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).map(|_| input)
+        }
+        Some(path) => fs::read_to_string(path),
+    };
+    let input = match input {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let report = inspect(&input, &args.schemes);
+    if args.json {
+        // maps of strings can always be serialized
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print!("{report}");
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "GET /users HTTP/1.1
+Host: example.com
+rpc-persist-tenant: t1
+rpc-transit-user_id: u1
+rpc-transit-metainfo-deadline-ms: 250
+rpc-backward-cost: 10
+Rpc-Persist-Tenant: t2
+rpc-persist-metainfo-hop-count: 1
+";
+
+    #[test]
+    fn test_http_dump() {
+        let report = inspect(DUMP, &[Scheme::Rpc, Scheme::Http]);
+        assert_eq!(report.persistent["TENANT"], "t2");
+        assert_eq!(report.transient["USER_ID"], "u1");
+        assert_eq!(report.backward["COST"], "10");
+        assert_eq!(report.timeout_ms.as_deref(), Some("250"));
+        // the hop count is incremented on import
+        assert_eq!(report.persistent["METAINFO_HOP_COUNT"], "2");
+        assert_eq!(
+            report.issues,
+            [
+                Issue {
+                    line: 2,
+                    key: "Host".into(),
+                    kind: IssueKind::Rejected,
+                    detail: "no rpc or http prefix".into(),
+                },
+                Issue {
+                    line: 3,
+                    key: "rpc-persist-tenant".into(),
+                    kind: IssueKind::Overwritten,
+                    detail: "persistent TENANT is set again by line 7".into(),
+                },
+                Issue {
+                    line: 4,
+                    key: "rpc-transit-user_id".into(),
+                    kind: IssueKind::Lossy,
+                    detail: "imported as USER_ID, re-exported as rpc-transit-user-id".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rpc_lines() {
+        let input =
+            "RPC_PERSIST_TENANT=t1\nRPC_TRANSIT_METAINFO_DEADLINE_MS=soon\nrpc-persist-x: y\n";
+        let report = inspect(input, &[Scheme::Rpc]);
+        assert_eq!(report.persistent["TENANT"], "t1");
+        assert!(report.timeout_ms.is_none());
+        let kinds: Vec<_> = report.issues.iter().map(|i| &i.kind).collect();
+        assert_eq!(kinds, [&IssueKind::Invalid, &IssueKind::Rejected]);
+        assert!(report
+            .to_string()
+            .contains("line 3: rpc-persist-x: rejected, no rpc prefix"));
+    }
+
+    #[test]
+    fn test_escaped() {
+        let input = "RPC-PERSIST-T.R.A.C.EI.D: t1\nrpc-persist-a~2eb: a\nrpc-transit-test-key: t\n";
+        let report = inspect(input, &[Scheme::Escaped]);
+        assert_eq!(report.persistent["TraceId"], "t1");
+        assert_eq!(report.persistent["A.B"], "a");
+        assert_eq!(report.transient["TEST_KEY"], "t");
+        // header names which only differ in case are not lossy
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse_args(["--scheme", "escaped", "dump.txt"].map(String::from)).unwrap();
+        assert_eq!(args.schemes, [Scheme::Escaped]);
+        assert!(!args.json);
+        assert_eq!(args.file.as_deref(), Some("dump.txt"));
+        assert!(parse_args(["--json".to_string()]).unwrap().json);
+        assert!(parse_args(["--scheme".to_string()]).is_err());
+        assert!(parse_args(["a", "b"].map(String::from)).is_err());
+    }
+}
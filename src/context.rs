@@ -0,0 +1,399 @@
+//! Request context for panics and errors.
+//!
+//! A [`MetaInfoSelector`] picks the keys worth reporting out of a `MetaInfo`, with an optional
+//! redaction hook. With the `task_local` feature, it can be installed as a panic hook appending
+//! the selected keys of the task local [`METAINFO`](crate::METAINFO) to panic reports, and used
+//! by [`MetaInfoContext`] to snapshot them into errors.
+//!
+//! Since [`MetaInfoFields`] implements `Display`, it can also be attached to `anyhow` errors with
+//! `.with_context(|| selector.current())`.
+//!
+//! Examples:
+//! ```rust
+//! # #[cfg(feature = "task_local")]
+//! # {
+//! use std::cell::RefCell;
+//!
+//! use metainfo::{Forward, MetaInfo, MetaInfoContext, MetaInfoSelector, METAINFO};
+//!
+//! let selector = MetaInfoSelector::new()
+//!     .persistent("TENANT")
+//!     .transient("REQUEST_ID")
+//!     .redact(|key, value| if key == "TENANT" { "***".into() } else { value.clone() });
+//!
+//! let mut mi = MetaInfo::new();
+//! mi.set_persistent("TENANT", "t1");
+//! mi.set_upstream("REQUEST_ID", "r1");
+//! let err = METAINFO.sync_scope(RefCell::new(mi), || {
+//!     "x".parse::<u32>().metainfo_context(&selector).unwrap_err()
+//! });
+//! assert_eq!(
+//!     err.to_string(),
+//!     "invalid digit found in string [TENANT=*** REQUEST_ID=r1]"
+//! );
+//! # }
+//! ```
+
+use std::{error::Error, fmt, sync::Arc};
+
+use faststr::FastStr;
+
+use crate::{Forward, MetaInfo};
+
+type Redact = Arc<dyn Fn(&str, &FastStr) -> FastStr + Send + Sync>;
+
+/// A snapshot of the selected entries of a `MetaInfo`.
+#[derive(Debug, Clone, Default)]
+pub struct MetaInfoFields {
+    fields: Vec<(FastStr, FastStr)>,
+}
+
+impl MetaInfoFields {
+    /// Get the value recorded for the given key.
+    #[inline]
+    pub fn get<K: AsRef<str>>(&self, key: K) -> Option<&FastStr> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key.as_ref())
+            .map(|(_, v)| v)
+    }
+
+    /// Iterate over the recorded entries in the configured order.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&FastStr, &FastStr)> {
+        self.fields.iter().map(|(k, v)| (k, v))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+}
+
+/// Formats the entries as `key=value` pairs separated by spaces.
+impl fmt::Display for MetaInfoFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (k, v)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{k}={v}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The keys of a `MetaInfo` to report, see the [module docs](self).
+#[derive(Clone, Default)]
+pub struct MetaInfoSelector {
+    persistents: Vec<FastStr>,
+    transients: Vec<FastStr>,
+    redact: Option<Redact>,
+}
+
+impl MetaInfoSelector {
+    /// Creates a `MetaInfoSelector` selecting nothing.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Select the given forward persistent key.
+    #[inline]
+    pub fn persistent<K: Into<FastStr>>(mut self, key: K) -> Self {
+        self.persistents.push(key.into());
+        self
+    }
+
+    /// Select the given forward transient key.
+    ///
+    /// The upstream value is selected if there is no transient value, since transients received
+    /// by a server are stored as upstreams.
+    #[inline]
+    pub fn transient<K: Into<FastStr>>(mut self, key: K) -> Self {
+        self.transients.push(key.into());
+        self
+    }
+
+    /// Set a hook to redact values before they are recorded.
+    ///
+    /// The hook is called with the key and the original value and returns the value to record.
+    #[inline]
+    pub fn redact<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, &FastStr) -> FastStr + Send + Sync + 'static,
+    {
+        self.redact = Some(Arc::new(f));
+        self
+    }
+
+    /// Snapshot the selected keys of the given `MetaInfo`.
+    pub fn fields(&self, mi: &MetaInfo) -> MetaInfoFields {
+        let persistents = self
+            .persistents
+            .iter()
+            .filter_map(|k| mi.get_persistent(k).map(|v| (k, v)));
+        let transients = self.transients.iter().filter_map(|k| {
+            mi.get_transient(k)
+                .or_else(|| mi.get_upstream(k))
+                .map(|v| (k, v))
+        });
+        let fields = persistents
+            .chain(transients)
+            .map(|(k, v)| match &self.redact {
+                Some(redact) => (k.clone(), redact(k, &v)),
+                None => (k.clone(), v),
+            })
+            .collect();
+        MetaInfoFields { fields }
+    }
+}
+
+impl fmt::Debug for MetaInfoSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetaInfoSelector")
+            .field("persistents", &self.persistents)
+            .field("transients", &self.transients)
+            .field("redact", &self.redact.is_some())
+            .finish()
+    }
+}
+
+#[cfg(feature = "task_local")]
+impl MetaInfoSelector {
+    /// Snapshot the selected keys of the task local [`METAINFO`](crate::METAINFO).
+    ///
+    /// Returns empty fields outside of a `METAINFO` scope, or if it is mutably borrowed.
+    pub fn current(&self) -> MetaInfoFields {
+        crate::METAINFO
+            .try_with(|mi| mi.try_borrow().map(|mi| self.fields(&mi)).ok())
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    /// Install a panic hook printing the selected keys of the task local
+    /// [`METAINFO`](crate::METAINFO) after the report of the previous hook, e.g.
+    /// `metainfo: TENANT=t1 REQUEST_ID=r1`.
+    ///
+    /// Nothing is printed for panics outside of a `METAINFO` scope.
+    pub fn install_panic_hook(self) {
+        self.install_panic_hook_to(std::io::stderr)
+    }
+
+    fn install_panic_hook_to<W: std::io::Write>(
+        self,
+        writer: impl Fn() -> W + Send + Sync + 'static,
+    ) {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            let fields = self.current();
+            if !fields.is_empty() {
+                // nothing sensible to do if the report can't be written
+                let _ = writeln!(writer(), "metainfo: {fields}");
+            }
+        }));
+    }
+}
+
+/// An error with a snapshot of the request context.
+///
+/// Displayed as the inner error followed by the fields, e.g. `timed out [TENANT=t1]`.
+#[derive(Debug, Clone)]
+pub struct WithMetaInfo<E> {
+    error: E,
+    fields: MetaInfoFields,
+}
+
+impl<E> WithMetaInfo<E> {
+    /// Attach the fields to an error.
+    #[inline]
+    pub fn new(error: E, fields: MetaInfoFields) -> Self {
+        WithMetaInfo { error, fields }
+    }
+
+    /// Get the attached fields.
+    #[inline]
+    pub fn fields(&self) -> &MetaInfoFields {
+        &self.fields
+    }
+
+    /// Get a reference to the inner error.
+    #[inline]
+    pub fn get_ref(&self) -> &E {
+        &self.error
+    }
+
+    /// Get the inner error.
+    #[inline]
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for WithMetaInfo<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)?;
+        if !self.fields.is_empty() {
+            write!(f, " [{}]", self.fields)?;
+        }
+        Ok(())
+    }
+}
+
+/// The inner error is displayed by [`WithMetaInfo`], so its source is the source of the inner
+/// error.
+impl<E: Error> Error for WithMetaInfo<E> {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Snapshot the request context into errors.
+#[cfg(feature = "task_local")]
+pub trait MetaInfoContext<T, E> {
+    /// Attach the selected keys of the task local [`METAINFO`](crate::METAINFO) to the error.
+    fn metainfo_context(self, selector: &MetaInfoSelector) -> Result<T, WithMetaInfo<E>>;
+}
+
+#[cfg(feature = "task_local")]
+impl<T, E: Error> MetaInfoContext<T, E> for Result<T, E> {
+    #[inline]
+    fn metainfo_context(self, selector: &MetaInfoSelector) -> Result<T, WithMetaInfo<E>> {
+        self.map_err(|error| WithMetaInfo::new(error, selector.current()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector() -> MetaInfoSelector {
+        MetaInfoSelector::new()
+            .persistent("TENANT")
+            .transient("REQUEST_ID")
+            .persistent("MISSING")
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TENANT", "t1");
+        mi.set_upstream("REQUEST_ID", "r1");
+        let fields = selector()
+            .redact(|key, value| {
+                if key == "REQUEST_ID" {
+                    FastStr::from_static_str("<redacted>")
+                } else {
+                    value.clone()
+                }
+            })
+            .fields(&mi);
+        assert_eq!(fields.to_string(), "TENANT=t1 REQUEST_ID=<redacted>");
+        assert_eq!(fields.get("TENANT").unwrap(), "t1");
+        assert!(fields.get("MISSING").is_none());
+    }
+
+    #[test]
+    fn test_with_metainfo() {
+        let err = std::io::Error::other("boom");
+        let wrapped = WithMetaInfo::new(err, MetaInfoFields::default());
+        assert_eq!(wrapped.to_string(), "boom");
+        assert!(wrapped.source().is_none());
+    }
+
+    #[cfg(feature = "task_local")]
+    #[test]
+    fn test_current() {
+        use std::cell::RefCell;
+
+        use crate::METAINFO;
+
+        let selector = selector();
+        assert!(selector.current().is_empty());
+        assert!(Err::<(), _>(std::fmt::Error)
+            .metainfo_context(&selector)
+            .unwrap_err()
+            .fields()
+            .is_empty());
+
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TENANT", "t1");
+        METAINFO.sync_scope(RefCell::new(mi), || {
+            assert_eq!(selector.current().to_string(), "TENANT=t1");
+            // the hook must not panic while the task local is mutably borrowed
+            METAINFO.with(|mi| {
+                let _guard = mi.borrow_mut();
+                assert!(selector.current().is_empty());
+            });
+            let err = Err::<(), _>(std::fmt::Error)
+                .metainfo_context(&selector)
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "an error occurred when formatting an argument [TENANT=t1]"
+            );
+        });
+    }
+
+    #[cfg(feature = "task_local")]
+    #[test]
+    fn test_panic_hook() {
+        use std::{
+            cell::RefCell,
+            io,
+            panic::{self, AssertUnwindSafe},
+            sync::{Arc, Mutex},
+        };
+
+        use crate::METAINFO;
+
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let captured = Captured::default();
+        selector().install_panic_hook_to({
+            let captured = captured.clone();
+            move || captured.clone()
+        });
+
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TENANT", "panic-hook");
+        mi.set_upstream("REQUEST_ID", "r1");
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            METAINFO.sync_scope(RefCell::new(mi), || panic!("boom"));
+        }));
+        // panics outside of a scope are reported by the previous hook only
+        let outside = panic::catch_unwind(|| panic!("outside"));
+        drop(panic::take_hook());
+
+        assert!(result.is_err() && outside.is_err());
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        // the hook is global, ignore the reports of the tests panicking meanwhile
+        assert_eq!(
+            output
+                .lines()
+                .filter(|line| line.contains("panic-hook"))
+                .collect::<Vec<_>>(),
+            ["metainfo: TENANT=panic-hook REQUEST_ID=r1"]
+        );
+    }
+}
@@ -96,7 +96,7 @@ pub struct HttpConverter;
 impl HttpConverter {
     /// Convert `RPC_PERSIST_TEST_KEY` to `rpc-persist-test-key`
     #[inline]
-    fn to_http_format(&self, key: &str, buf: &mut [u8]) {
+    fn to_http_format(self, key: &str, buf: &mut [u8]) {
         let mut l = 0;
         for ch in key.chars() {
             let ch = match ch {
@@ -123,7 +123,7 @@ impl HttpConverter {
 
     /// Convert `rpc-persist-test-key` to `RPC_PERSIST_TEST_KEY`
     #[inline]
-    fn to_rpc_format(&self, key: &str, buf: &mut [u8]) {
+    fn to_rpc_format(self, key: &str, buf: &mut [u8]) {
         let mut l = 0;
         for ch in key.chars() {
             let ch = match ch {
@@ -171,13 +171,29 @@ impl HttpConverter {
         unsafe {
             buf.set_len(prefix.len() + key.len());
         }
-        self.to_http_format(key, &mut buf);
+        self.to_http_format(key, &mut buf[prefix.len()..]);
         unsafe { FastStr::from_vec_u8_unchecked(buf) }
     }
 
+    /// Strip the ascii prefix ignoring case, since proxies may normalize the header names, e.g.
+    /// `Rpc-Persist-Test-Key`.
+    #[inline]
+    fn strip_prefix_ignore_case<'a>(&self, prefix: &'static str, key: &'a str) -> Option<&'a str> {
+        if let Some(key) = key.strip_prefix(prefix) {
+            return Some(key);
+        }
+        let head = key.as_bytes().get(..prefix.len())?;
+        if head.eq_ignore_ascii_case(prefix.as_bytes()) {
+            // the head is ascii as the prefix, so this is a char boundary
+            Some(&key[prefix.len()..])
+        } else {
+            None
+        }
+    }
+
     #[inline]
     fn remove_prefix_and_to_rpc_format(&self, prefix: &'static str, key: &str) -> Option<FastStr> {
-        let key = key.strip_prefix(prefix)?;
+        let key = self.strip_prefix_ignore_case(prefix, key)?;
 
         // checks if we can use the inline buffer to reduce heap allocations
         if key.len() <= FASTSTR_INLINE_SIZE {
@@ -227,9 +243,123 @@ impl Converter for HttpConverter {
     }
 }
 
+/// The http format with escaping, which round-trips arbitrary keys.
+///
+/// Keys made of uppercase letters, digits and `_` are converted as by [`HttpConverter`]. Other
+/// chars are escaped with chars which are valid in header names and survive case normalization:
+/// - a lowercase letter is written as `.` followed by the letter, e.g. `TraceId` is
+///   `t.r.a.c.ei.d`;
+/// - any other byte is written as `~` followed by two hex digits, e.g. `USER-ID` is `user~2did`.
+///
+/// Header names written by [`HttpConverter`] for keys containing `.` or `~` can't be read back.
+#[derive(Clone, Copy)]
+pub struct EscapedHttpConverter;
+
+impl EscapedHttpConverter {
+    /// Check if the key is converted as by [`HttpConverter`].
+    #[inline]
+    fn is_plain(key: &str) -> bool {
+        key.bytes()
+            .all(|b| matches!(b, b'A'..=b'Z' | b'0'..=b'9' | b'_'))
+    }
+
+    #[inline]
+    fn add_prefix_and_escape(&self, prefix: &'static str, key: &str) -> FastStr {
+        if Self::is_plain(key) {
+            return HttpConverter.add_prefix_and_to_http_format(prefix, key);
+        }
+        let mut res = String::with_capacity(prefix.len() + key.len() * 2);
+        res.push_str(prefix);
+        for b in key.bytes() {
+            match b {
+                b'A'..=b'Z' => res.push(b.to_ascii_lowercase() as char),
+                b'0'..=b'9' => res.push(b as char),
+                b'_' => res.push('-'),
+                b'a'..=b'z' => {
+                    res.push('.');
+                    res.push(b as char);
+                }
+                _ => {
+                    const HEX: &[u8; 16] = b"0123456789abcdef";
+                    res.push('~');
+                    res.push(HEX[(b >> 4) as usize] as char);
+                    res.push(HEX[(b & 0xf) as usize] as char);
+                }
+            }
+        }
+        FastStr::from_string(res)
+    }
+
+    #[inline]
+    fn remove_prefix_and_unescape(&self, prefix: &'static str, key: &str) -> Option<FastStr> {
+        let escaped = HttpConverter.strip_prefix_ignore_case(prefix, key)?;
+        if !escaped.contains(['.', '~']) {
+            return HttpConverter.remove_prefix_and_to_rpc_format(prefix, key);
+        }
+
+        fn hex(b: u8) -> Option<u8> {
+            (b as char).to_digit(16).map(|d| d as u8)
+        }
+        let mut res = Vec::with_capacity(escaped.len());
+        let mut bytes = escaped.bytes();
+        while let Some(b) = bytes.next() {
+            match b {
+                b'.' => match bytes.next()? {
+                    c @ (b'a'..=b'z' | b'A'..=b'Z') => res.push(c.to_ascii_lowercase()),
+                    _ => return None,
+                },
+                b'~' => {
+                    let hi = hex(bytes.next()?)?;
+                    let lo = hex(bytes.next()?)?;
+                    res.push(hi << 4 | lo);
+                }
+                b'a'..=b'z' => res.push(b.to_ascii_uppercase()),
+                b'-' => res.push(b'_'),
+                _ => res.push(b),
+            }
+        }
+        String::from_utf8(res).ok().map(FastStr::from_string)
+    }
+}
+
+impl Converter for EscapedHttpConverter {
+    #[inline]
+    fn add_persistent_prefix(&self, key: &str) -> FastStr {
+        self.add_prefix_and_escape(HTTP_PREFIX_PERSISTENT, key)
+    }
+
+    #[inline]
+    fn add_transient_prefix(&self, key: &str) -> FastStr {
+        self.add_prefix_and_escape(HTTP_PREFIX_TRANSIENT, key)
+    }
+
+    #[inline]
+    fn add_backward_prefix(&self, key: &str) -> FastStr {
+        self.add_prefix_and_escape(HTTP_PREFIX_BACKWARD, key)
+    }
+
+    #[inline]
+    fn remove_persistent_prefix(&self, key: &str) -> Option<FastStr> {
+        self.remove_prefix_and_unescape(HTTP_PREFIX_PERSISTENT, key)
+    }
+
+    #[inline]
+    fn remove_transient_prefix(&self, key: &str) -> Option<FastStr> {
+        self.remove_prefix_and_unescape(HTTP_PREFIX_TRANSIENT, key)
+    }
+
+    #[inline]
+    fn remove_backward_prefix(&self, key: &str) -> Option<FastStr> {
+        self.remove_prefix_and_unescape(HTTP_PREFIX_BACKWARD, key)
+    }
+}
+
 #[cfg(test)]
 mod convert_tests {
-    use crate::convert::{Converter, HttpConverter, RpcConverter};
+    use crate::{
+        convert::{Converter, EscapedHttpConverter, HttpConverter, RpcConverter},
+        testing::XorShift,
+    };
 
     #[test]
     fn add_rpc_prefix() {
@@ -337,7 +467,7 @@ mod convert_tests {
     }
 
     impl HttpConverter {
-        fn to_http_format_string(&self, key: &str) -> String {
+        fn to_http_format_string(self, key: &str) -> String {
             let mut buf = Vec::with_capacity(key.len());
             unsafe {
                 buf.set_len(key.len());
@@ -346,7 +476,7 @@ mod convert_tests {
             String::from_utf8(buf).unwrap()
         }
 
-        fn to_rpc_format_string(&self, key: &str) -> String {
+        fn to_rpc_format_string(self, key: &str) -> String {
             let mut buf = Vec::with_capacity(key.len());
             unsafe {
                 buf.set_len(key.len());
@@ -356,6 +486,47 @@ mod convert_tests {
         }
     }
 
+    #[test]
+    fn remove_http_prefix_ignore_case() {
+        assert_eq!(
+            HttpConverter
+                .remove_persistent_prefix("Rpc-Persist-Test-Key")
+                .as_deref(),
+            Some("TEST_KEY"),
+        );
+        assert_eq!(
+            HttpConverter
+                .remove_transient_prefix("RPC-TRANSIT-TEST-KEY")
+                .as_deref(),
+            Some("TEST_KEY"),
+        );
+        assert_eq!(
+            HttpConverter
+                .remove_backward_prefix("rpc-Backward-test-key")
+                .as_deref(),
+            Some("TEST_KEY"),
+        );
+        assert_eq!(
+            HttpConverter
+                .remove_persistent_prefix("RPC_PERSIST_TEST_KEY")
+                .as_deref(),
+            None,
+        );
+        assert_eq!(
+            HttpConverter
+                .remove_persistent_prefix("rpc-persis")
+                .as_deref(),
+            None,
+        );
+        // a multi-byte char across the prefix length
+        assert_eq!(
+            HttpConverter
+                .remove_persistent_prefix("rpc-persist\u{e9}")
+                .as_deref(),
+            None,
+        );
+    }
+
     #[test]
     fn http_format_convert_test() {
         fn check(rpc_style: &str, http_style: &str) {
@@ -417,6 +588,11 @@ mod convert_tests {
             HttpConverter.add_backward_prefix("TEST_KEY"),
             "rpc-backward-test-key",
         );
+        // longer than the inline buffer
+        assert_eq!(
+            HttpConverter.add_transient_prefix("METAINFO_DEADLINE_MS"),
+            "rpc-transit-metainfo-deadline-ms",
+        );
     }
 
     #[test]
@@ -507,4 +683,91 @@ mod convert_tests {
             "rpc-backward-test-key",
         );
     }
+
+    #[test]
+    fn escaped_http_format() {
+        fn check(key: &str, http_style: &str) {
+            assert_eq!(EscapedHttpConverter.add_persistent_prefix(key), http_style);
+            assert_eq!(
+                EscapedHttpConverter
+                    .remove_persistent_prefix(http_style)
+                    .as_deref(),
+                Some(key),
+            );
+        }
+        // compatible with the plain http format
+        check("TEST_KEY", "rpc-persist-test-key");
+        check("KEY_2", "rpc-persist-key-2");
+        check("TraceId", "rpc-persist-t.r.a.c.ei.d");
+        check("user-id", "rpc-persist-.u.s.e.r~2d.i.d");
+        check("A.B~C", "rpc-persist-a~2eb~7ec");
+        check("k\u{e9}", "rpc-persist-.k~c3~a9");
+
+        // normalized by proxies
+        assert_eq!(
+            EscapedHttpConverter
+                .remove_persistent_prefix("Rpc-Persist-T.R.A.C.Ei.D")
+                .as_deref(),
+            Some("TraceId"),
+        );
+        assert_eq!(
+            EscapedHttpConverter
+                .remove_persistent_prefix("RPC-PERSIST-A~2EB")
+                .as_deref(),
+            Some("A.B"),
+        );
+
+        // invalid escapes
+        for invalid in [
+            "rpc-persist-a.",
+            "rpc-persist-a.1",
+            "rpc-persist-a~2",
+            "rpc-persist-~zz",
+        ] {
+            assert_eq!(
+                EscapedHttpConverter
+                    .remove_persistent_prefix(invalid)
+                    .as_deref(),
+                None,
+                "{invalid}",
+            );
+        }
+        // an escaped byte sequence which is not utf-8
+        assert_eq!(
+            EscapedHttpConverter
+                .remove_persistent_prefix("rpc-persist-~ff")
+                .as_deref(),
+            None,
+        );
+    }
+
+    #[test]
+    fn escaped_http_round_trip() {
+        let mut rng = XorShift::new();
+        let chars: Vec<char> = ('\0'..='\u{7f}')
+            .chain(['\u{e9}', '\u{4e2d}', '\u{1f600}'])
+            .collect();
+        for _ in 0..10_000 {
+            let key: String = (0..rng.next() % 16)
+                .map(|_| chars[(rng.next() % chars.len() as u64) as usize])
+                .collect();
+            let escaped = EscapedHttpConverter.add_transient_prefix(&key);
+            assert!(escaped
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-.~".contains(&b)));
+            assert_eq!(
+                EscapedHttpConverter
+                    .remove_transient_prefix(&escaped)
+                    .as_deref(),
+                Some(key.as_str()),
+            );
+            // header names may be normalized to uppercase
+            assert_eq!(
+                EscapedHttpConverter
+                    .remove_transient_prefix(&escaped.to_ascii_uppercase())
+                    .as_deref(),
+                Some(key.as_str()),
+            );
+        }
+    }
 }
@@ -0,0 +1,215 @@
+//! Deadline propagation through [`MetaInfo`].
+//!
+//! The deadline is stored as an absolute [`Instant`] in the typed map, so it's inherited by
+//! derived `MetaInfo`s like any other typed entry.
+//!
+//! Since `Instant`s are meaningless across processes, the deadline is transported as the remaining
+//! timeout in milliseconds under the transient key [`DEADLINE_TIMEOUT_KEY`]:
+//! - the forward transients exported by `get_all_*` and the [`Propagator`](crate::Propagator)s
+//!   include the remaining timeout, users of the `iter_*` iterators send the entry of
+//!   `deadline_header_with_{rpc,http}_prefix` along with them;
+//! - the imported remaining timeout is converted back to an absolute deadline, minus
+//!   [`DEADLINE_SKEW_MARGIN`] to account for the time spent on the wire. A value which is not a
+//!   number of milliseconds is set as an upstream like any other transient.
+
+use std::time::{Duration, Instant};
+
+use faststr::FastStr;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, Direction};
+use crate::{
+    convert::{Converter, HttpConverter, RpcConverter},
+    MetaInfo,
+};
+
+/// The transient key used to transport the remaining timeout in milliseconds.
+pub const DEADLINE_TIMEOUT_KEY: &str = "METAINFO_DEADLINE_MS";
+
+/// The safety margin subtracted from the remaining timeout when importing a deadline.
+pub const DEADLINE_SKEW_MARGIN: Duration = Duration::from_millis(5);
+
+struct Deadline(Instant);
+
+impl MetaInfo {
+    /// Set the absolute deadline of the current request into this `MetaInfo`.
+    #[inline]
+    #[track_caller]
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.insert(Deadline(deadline));
+    }
+
+    /// Set the deadline of the current request to `timeout` from now.
+    #[inline]
+    #[track_caller]
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.set_deadline(Instant::now() + timeout);
+    }
+
+    /// Get the deadline of the current request.
+    #[inline]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.get::<Deadline>().map(|d| d.0)
+    }
+
+    /// Get the remaining time until the deadline, `Duration::ZERO` if it has passed.
+    #[inline]
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline()
+            .map(|d| d.saturating_duration_since(Instant::now()))
+    }
+
+    /// Check if the deadline has passed. Returns `false` if there is no deadline.
+    #[inline]
+    pub fn is_expired(&self) -> bool {
+        self.deadline()
+            .map(|d| d <= Instant::now())
+            .unwrap_or(false)
+    }
+
+    /// Remove the deadline from this `MetaInfo` and return it.
+    /// Can only remove the deadline in the current scope.
+    #[inline]
+    #[track_caller]
+    pub fn remove_deadline(&mut self) -> Option<Instant> {
+        self.remove::<Deadline>().map(|d| d.0)
+    }
+
+    /// Set the deadline from a remaining timeout in milliseconds received from the upstream,
+    /// minus the given safety margin.
+    ///
+    /// Returns `false` and leaves the deadline untouched if the value is not a valid timeout.
+    #[inline]
+    #[track_caller]
+    pub fn set_deadline_from_timeout_ms(&mut self, value: &str, margin: Duration) -> bool {
+        match value.parse::<u64>() {
+            Ok(ms) => {
+                let timeout = Duration::from_millis(ms).saturating_sub(margin);
+                self.set_timeout(timeout);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Get the remaining timeout as a transient entry with rpc prefix, to be sent along with the
+    /// entries of `iter_persistents_and_transients_with_rpc_prefix`.
+    #[inline]
+    pub fn deadline_header_with_rpc_prefix(&self) -> Option<(FastStr, FastStr)> {
+        self.deadline_header_with_prefix(RpcConverter)
+    }
+
+    /// Get the remaining timeout as a transient entry with http prefix, to be sent along with the
+    /// entries of `iter_persistents_and_transients_with_http_prefix`.
+    #[inline]
+    pub fn deadline_header_with_http_prefix(&self) -> Option<(FastStr, FastStr)> {
+        self.deadline_header_with_prefix(HttpConverter)
+    }
+
+    #[inline]
+    pub(crate) fn deadline_header_with_prefix<C: Converter>(
+        &self,
+        converter: C,
+    ) -> Option<(FastStr, FastStr)> {
+        let timeout = self.deadline_timeout_ms()?;
+        let key = converter.add_transient_prefix(DEADLINE_TIMEOUT_KEY);
+        #[cfg(feature = "metrics")]
+        metrics::record_entry(Direction::Export, crate::Section::Transient, &key, &timeout);
+        Some((key, timeout))
+    }
+
+    /// Get the remaining timeout in milliseconds, the value of [`DEADLINE_TIMEOUT_KEY`].
+    #[inline]
+    pub(crate) fn deadline_timeout_ms(&self) -> Option<FastStr> {
+        self.remaining()
+            .map(|remaining| FastStr::from_string(remaining.as_millis().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Forward;
+
+    #[test]
+    fn test_deadline() {
+        let mut mi = MetaInfo::new();
+        assert!(mi.deadline().is_none());
+        assert!(!mi.is_expired());
+
+        mi.set_timeout(Duration::from_secs(10));
+        let remaining = mi.remaining().unwrap();
+        assert!(remaining > Duration::from_secs(9));
+
+        let (_, child) = mi.derive();
+        assert!(child.deadline().is_some());
+
+        let mut mi = MetaInfo::new();
+        mi.set_deadline(Instant::now());
+        assert!(mi.is_expired());
+        assert_eq!(mi.remaining(), Some(Duration::ZERO));
+        assert!(mi.remove_deadline().is_some());
+        assert!(mi.deadline().is_none());
+    }
+
+    #[test]
+    fn test_deadline_propagation() {
+        let mut client = MetaInfo::new();
+        client.set_timeout(Duration::from_secs(10));
+
+        for (map, key) in [
+            (
+                client
+                    .get_all_persistents_and_transients_with_rpc_prefix()
+                    .unwrap(),
+                "RPC_TRANSIT_METAINFO_DEADLINE_MS",
+            ),
+            (
+                client
+                    .get_all_persistents_and_transients_with_http_prefix()
+                    .unwrap(),
+                "rpc-transit-metainfo-deadline-ms",
+            ),
+        ] {
+            let timeout: u64 = map.get(key).unwrap().parse().unwrap();
+            assert!(timeout > 9000 && timeout <= 10000);
+        }
+
+        // the iterators don't include the remaining timeout
+        assert_eq!(
+            client
+                .iter_persistents_and_transients_with_rpc_prefix()
+                .count(),
+            0
+        );
+        let (k, v) = client.deadline_header_with_rpc_prefix().unwrap();
+        assert_eq!(k, "RPC_TRANSIT_METAINFO_DEADLINE_MS");
+        let mut server = MetaInfo::new();
+        server.strip_rpc_prefix_and_set_upstream(k, v);
+        assert!(server.get_upstream(DEADLINE_TIMEOUT_KEY).is_none());
+        let remaining = server.remaining().unwrap();
+        assert!(remaining > Duration::from_secs(9) && remaining < Duration::from_secs(10));
+
+        let (k, v) = client.deadline_header_with_http_prefix().unwrap();
+        assert_eq!(k, "rpc-transit-metainfo-deadline-ms");
+        let mut server = MetaInfo::new();
+        server.strip_http_prefix_and_set_upstream(k, v);
+        assert!(server.deadline().is_some());
+    }
+
+    #[test]
+    fn test_invalid_timeout() {
+        let mut server = MetaInfo::new();
+        server.strip_rpc_prefix_and_set_upstream("RPC_TRANSIT_METAINFO_DEADLINE_MS", "invalid");
+        assert!(server.deadline().is_none());
+        assert_eq!(
+            server.get_upstream(DEADLINE_TIMEOUT_KEY).unwrap(),
+            "invalid"
+        );
+
+        // an application key which happens to be named like the old key is left alone
+        server.strip_rpc_prefix_and_set_upstream("RPC_TRANSIT_TIMEOUT_MS", "100");
+        assert_eq!(server.get_upstream("TIMEOUT_MS").unwrap(), "100");
+        assert!(server.deadline().is_none());
+    }
+}
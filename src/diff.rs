@@ -0,0 +1,296 @@
+//! Comparison of two [`MetaInfo`]s.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+
+use crate::{Backward, Forward, MetaInfo, Section};
+
+/// The kind of a [`DiffEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// An entry which differs between two [`MetaInfo`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEntry {
+    /// The section of the entry.
+    pub section: Section,
+    /// The key of the entry, or the type name for the typed and faststr sections, which is only
+    /// readable with the `type_names` feature.
+    pub key: FastStr,
+    /// How the entry differs.
+    pub kind: DiffKind,
+    /// The value on the left side, `None` if added or for typed entries.
+    pub old: Option<FastStr>,
+    /// The value on the right side, `None` if removed or for typed entries.
+    pub new: Option<FastStr>,
+}
+
+/// The differences between two [`MetaInfo`]s, returned by [`MetaInfo::diff`].
+///
+/// Entries are sorted by section and key.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MetaInfoDiff {
+    pub entries: Vec<DiffEntry>,
+}
+
+impl MetaInfoDiff {
+    /// Check if there is no difference.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the differences of the given section.
+    pub fn section(&self, section: Section) -> impl Iterator<Item = &DiffEntry> {
+        self.entries.iter().filter(move |e| e.section == section)
+    }
+}
+
+/// One line per entry, e.g. `~ persistent TENANT: "t1" -> "t2"`.
+impl fmt::Display for MetaInfoDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            let sign = match e.kind {
+                DiffKind::Added => '+',
+                DiffKind::Removed => '-',
+                DiffKind::Changed => '~',
+            };
+            write!(f, "{sign} {} {}", e.section, e.key)?;
+            match (&e.old, &e.new) {
+                (Some(old), Some(new)) => write!(f, ": {old:?} -> {new:?}")?,
+                (Some(v), None) | (None, Some(v)) => write!(f, ": {v:?}")?,
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn diff_map(
+    section: Section,
+    a: Option<&AHashMap<FastStr, FastStr>>,
+    b: Option<&AHashMap<FastStr, FastStr>>,
+    entries: &mut Vec<DiffEntry>,
+) {
+    let mut keys = BTreeMap::new();
+    for (k, v) in a.into_iter().flatten() {
+        keys.entry(k).or_insert((None, None)).0 = Some(v);
+    }
+    for (k, v) in b.into_iter().flatten() {
+        keys.entry(k).or_insert((None, None)).1 = Some(v);
+    }
+    for (k, (old, new)) in keys {
+        let kind = match (old, new) {
+            (Some(old), Some(new)) if old == new => continue,
+            (Some(_), Some(_)) => DiffKind::Changed,
+            (Some(_), None) => DiffKind::Removed,
+            _ => DiffKind::Added,
+        };
+        entries.push(DiffEntry {
+            section,
+            key: k.clone(),
+            kind,
+            old: old.cloned(),
+            new: new.cloned(),
+        });
+    }
+}
+
+fn diff_types(
+    section: Section,
+    a: BTreeSet<FastStr>,
+    b: BTreeSet<FastStr>,
+    entries: &mut Vec<DiffEntry>,
+) {
+    let removed = a.difference(&b).map(|name| (name, DiffKind::Removed));
+    let added = b.difference(&a).map(|name| (name, DiffKind::Added));
+    let mut changes: Vec<_> = removed.chain(added).collect();
+    changes.sort_by_key(|(name, _)| *name);
+    for (name, kind) in changes {
+        entries.push(DiffEntry {
+            section,
+            key: name.clone(),
+            kind,
+            old: None,
+            new: None,
+        });
+    }
+}
+
+impl MetaInfo {
+    /// Get the type names of the typed and faststr entries, including the parents.
+    pub(crate) fn type_names(&self) -> (BTreeSet<FastStr>, BTreeSet<FastStr>) {
+        let mut typed = BTreeSet::new();
+        let mut faststr = BTreeSet::new();
+        let mut cur = Some(self);
+        while let Some(mi) = cur {
+            if let Some(tmap) = mi.tmap.as_ref() {
+                typed.extend(tmap.type_names());
+            }
+            if let Some(faststr_tmap) = mi.faststr_tmap.as_ref() {
+                faststr.extend(faststr_tmap.type_names());
+            }
+            cur = mi.parent.as_deref();
+        }
+        (typed, faststr)
+    }
+
+    /// Get the faststr entries by type name, including the parents.
+    pub(crate) fn flatten_faststrs(&self) -> AHashMap<FastStr, FastStr> {
+        let mut faststrs = AHashMap::new();
+        let mut cur = Some(self);
+        while let Some(mi) = cur {
+            if let Some(faststr_tmap) = mi.faststr_tmap.as_ref() {
+                for (name, v) in faststr_tmap.named_iter() {
+                    // the entries of the children shadow the ones of the parents
+                    faststrs.entry(name).or_insert_with(|| v.clone());
+                }
+            }
+            cur = mi.parent.as_deref();
+        }
+        faststrs
+    }
+
+    /// Compare self with `other`, entries only in `other` are reported as added.
+    ///
+    /// The comparison is done on the effective view, i.e. including the entries inherited from
+    /// the parents. Faststr entries are compared by value under their type name, typed entries
+    /// by type name only, since their values can't be compared.
+    pub fn diff(&self, other: &MetaInfo) -> MetaInfoDiff {
+        let mut entries = Vec::new();
+
+        let (a_typed, _) = self.type_names();
+        let (b_typed, _) = other.type_names();
+        diff_types(Section::Typed, a_typed, b_typed, &mut entries);
+        diff_map(
+            Section::FastStr,
+            Some(&self.flatten_faststrs()),
+            Some(&other.flatten_faststrs()),
+            &mut entries,
+        );
+
+        diff_map(
+            Section::String,
+            Some(&self.flatten_strings()),
+            Some(&other.flatten_strings()),
+            &mut entries,
+        );
+        diff_map(
+            Section::Persistent,
+            self.get_all_persistents(),
+            other.get_all_persistents(),
+            &mut entries,
+        );
+        diff_map(
+            Section::Transient,
+            self.get_all_transients(),
+            other.get_all_transients(),
+            &mut entries,
+        );
+        diff_map(
+            Section::Upstream,
+            self.get_all_upstreams(),
+            other.get_all_upstreams(),
+            &mut entries,
+        );
+        diff_map(
+            Section::BackwardTransient,
+            self.get_all_backward_transients(),
+            other.get_all_backward_transients(),
+            &mut entries,
+        );
+        diff_map(
+            Section::BackwardDownstream,
+            self.get_all_backward_downstreams(),
+            other.get_all_backward_downstreams(),
+            &mut entries,
+        );
+
+        MetaInfoDiff { entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::type_map::TypeNames;
+
+    #[test]
+    fn test_diff() {
+        let mut parent = MetaInfo::new();
+        parent.insert(1u8);
+        parent.insert_string("region".into(), "a".into());
+        let parent = Arc::new(parent);
+
+        let mut before = MetaInfo::from(parent.clone());
+        before.set_persistent("TENANT", "t1");
+        before.set_transient("USER", "u1");
+
+        let mut after = MetaInfo::from(parent);
+        after.insert(2u16);
+        after.insert_string("region".into(), "b".into());
+        after.set_persistent("TENANT", "t2");
+        after.set_backward_downstream("COST", "10");
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.to_string(),
+            [
+                format!("+ typed {}", TypeNames::name_of::<u16>()).as_str(),
+                "~ strings region: \"a\" -> \"b\"",
+                "~ persistent TENANT: \"t1\" -> \"t2\"",
+                "- transient USER: \"u1\"",
+                "+ backward_downstream COST: \"10\"",
+            ]
+            .join("\n")
+        );
+        assert_eq!(diff.section(Section::Transient).count(), 1);
+
+        assert!(before.diff(&before).is_empty());
+        let (before, derived) = before.derive();
+        assert!(MetaInfo::diff(&before, &derived).is_empty());
+    }
+
+    #[test]
+    fn test_diff_faststr() {
+        struct Rid;
+
+        let mut a = MetaInfo::new();
+        a.insert_faststr::<Rid>("a".into());
+        let mut b = MetaInfo::new();
+        b.insert_faststr::<Rid>("b".into());
+
+        let diff = a.diff(&b);
+        assert!(!diff.is_empty());
+        assert_eq!(
+            diff.entries,
+            [DiffEntry {
+                section: Section::FastStr,
+                key: TypeNames::name_of::<Rid>(),
+                kind: DiffKind::Changed,
+                old: Some("a".into()),
+                new: Some("b".into()),
+            }]
+        );
+
+        // the entries of the derived scope shadow the inherited ones
+        let (a, mut derived) = a.derive();
+        assert!(a.diff(&derived).is_empty());
+        derived.insert_faststr::<Rid>("b".into());
+        assert!(b.diff(&derived).is_empty());
+    }
+}
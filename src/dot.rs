@@ -0,0 +1,207 @@
+//! Graphviz export of the scope tree of [`MetaInfo`].
+
+use std::{collections::HashMap, fmt::Write};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+
+use crate::{MetaInfo, Section};
+
+/// Escape a string for a double-quoted dot label.
+fn escape(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+}
+
+fn label_names(section: Section, names: impl Iterator<Item = FastStr>, out: &mut String) {
+    let mut names: Vec<_> = names.collect();
+    if names.is_empty() {
+        return;
+    }
+    names.sort_unstable();
+    let _ = write!(out, "{section}:\\l");
+    for name in names {
+        out.push_str("  ");
+        escape(&name, out);
+        out.push_str("\\l");
+    }
+}
+
+fn label_map(section: Section, map: Option<&AHashMap<FastStr, FastStr>>, out: &mut String) {
+    let Some(map) = map.filter(|map| !map.is_empty()) else {
+        return;
+    };
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_unstable();
+    let _ = write!(out, "{section}:\\l");
+    for (k, v) in entries {
+        out.push_str("  ");
+        escape(k, out);
+        out.push_str(" = ");
+        escape(v, out);
+        out.push_str("\\l");
+    }
+}
+
+impl MetaInfo {
+    /// The label of the entries in the current scope.
+    fn dot_label(&self) -> String {
+        let mut label = String::new();
+        if let Some(tmap) = self.tmap.as_ref() {
+            label_names(Section::Typed, tmap.type_names(), &mut label);
+        }
+        if let Some(faststr_tmap) = self.faststr_tmap.as_ref() {
+            label_names(Section::FastStr, faststr_tmap.type_names(), &mut label);
+        }
+        label_map(Section::String, self.smap.as_ref(), &mut label);
+        if let Some(node) = self.forward_node.as_ref() {
+            label_map(Section::Persistent, node.get_all_persistents(), &mut label);
+            label_map(Section::Transient, node.get_all_transients(), &mut label);
+            label_map(Section::Upstream, node.get_all_stales(), &mut label);
+        }
+        if let Some(node) = self.backward_node.as_ref() {
+            label_map(
+                Section::BackwardTransient,
+                node.get_all_transients(),
+                &mut label,
+            );
+            label_map(
+                Section::BackwardDownstream,
+                node.get_all_stales(),
+                &mut label,
+            );
+        }
+        if label.is_empty() {
+            label.push_str("(empty)\\l");
+        }
+        label
+    }
+
+    /// Export the scope chain of this `MetaInfo` in the Graphviz dot format.
+    ///
+    /// See [`MetaInfo::tree_to_dot`].
+    #[inline]
+    pub fn to_dot(&self) -> String {
+        MetaInfo::tree_to_dot([self])
+    }
+
+    /// Export the scope tree of the given leaves in the Graphviz dot format.
+    ///
+    /// Each scope is a node labelled with its own entries, with an edge from its parent. The
+    /// ancestors shared by several leaves, i.e. the same `Arc`, are only exported once. The leaves
+    /// are drawn with a double border.
+    ///
+    /// Examples:
+    /// ```rust
+    /// use metainfo::MetaInfo;
+    ///
+    /// let mut mi = MetaInfo::new();
+    /// mi.insert_string("region".into(), "a".into());
+    /// let (left, right) = mi.derive();
+    ///
+    /// let dot = MetaInfo::tree_to_dot([&left, &right]);
+    /// assert_eq!(dot.matches("region = a").count(), 1);
+    /// assert_eq!(dot.matches(" -> ").count(), 2);
+    /// ```
+    pub fn tree_to_dot<'a, I>(leaves: I) -> String
+    where
+        I: IntoIterator<Item = &'a MetaInfo>,
+    {
+        let mut ids: HashMap<*const MetaInfo, usize> = HashMap::new();
+        let mut nodes = String::new();
+        let mut edges = String::new();
+
+        for leaf in leaves {
+            let mut cur = leaf;
+            let mut is_leaf = true;
+            loop {
+                let ptr = cur as *const MetaInfo;
+                if ids.contains_key(&ptr) {
+                    break;
+                }
+                let id = ids.len();
+                ids.insert(ptr, id);
+                let _ = write!(nodes, "  n{id} [label=\"{}\"", cur.dot_label());
+                if is_leaf {
+                    nodes.push_str(", peripheries=2");
+                }
+                nodes.push_str("];\n");
+
+                let Some(parent) = cur.parent.as_deref() else {
+                    break;
+                };
+                // the parent is exported with the next id if it's not already
+                let parent_id = ids
+                    .get(&(parent as *const MetaInfo))
+                    .copied()
+                    .unwrap_or(ids.len());
+                let _ = writeln!(edges, "  n{parent_id} -> n{id};");
+                cur = parent;
+                is_leaf = false;
+            }
+        }
+
+        format!(
+            "digraph metainfo {{\n  node [shape=box, fontname=\"monospace\"];\n{nodes}{edges}}}\n"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{type_map::TypeNames, Forward};
+
+    #[test]
+    fn test_to_dot() {
+        let mut mi = MetaInfo::new();
+        mi.insert(1u8);
+        mi.insert_string("quote".into(), "say \"hi\"".into());
+        mi.set_persistent("TENANT", "t1");
+        assert_eq!(
+            mi.to_dot(),
+            format!(
+                "digraph metainfo {{\n  node [shape=box, fontname=\"monospace\"];\n  n0 \
+                 [label=\"typed:\\l  {}\\l\
+                 strings:\\l  quote = say \\\"hi\\\"\\l\
+                 persistent:\\l  TENANT = t1\\l\", peripheries=2];\n}}\n",
+                TypeNames::name_of::<u8>()
+            )
+        );
+    }
+
+    #[test]
+    fn test_shared_ancestors() {
+        let mut root = MetaInfo::new();
+        root.insert_string("k".into(), "root".into());
+        let root = Arc::new(root);
+
+        let mut a = MetaInfo::from(root.clone());
+        a.insert_string("k".into(), "a".into());
+        let a = Arc::new(a);
+        let a1 = MetaInfo::from(a.clone());
+        let a2 = MetaInfo::from(a);
+        let b = MetaInfo::from(root);
+
+        let dot = MetaInfo::tree_to_dot([&a1, &a2, &b]);
+        assert_eq!(dot.matches("[label=").count(), 5);
+        assert_eq!(dot.matches("peripheries=2").count(), 3);
+        assert_eq!(dot.matches("k = root").count(), 1);
+        assert_eq!(dot.matches("k = a").count(), 1);
+        let mut edges: Vec<_> = dot.lines().filter(|l| l.contains("->")).collect();
+        edges.sort_unstable();
+        assert_eq!(
+            edges,
+            ["  n1 -> n0;", "  n1 -> n3;", "  n2 -> n1;", "  n2 -> n4;"]
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! Compatibility with Dubbo/Triple attachments.
+//!
+//! Attachments are string k-v pairs, any [`Carrier`] can be used to hold them. Entries are mapped
+//! to attachment keys in the http format, e.g. the persistent `TEST_KEY` is sent as
+//! `rpc-persist-test-key`, since Triple carries attachments as lowercase HTTP/2 headers.
+//!
+//! Java services usually read plain attachments, so [`DubboPropagator::plain_transients`] sends
+//! the transients without prefix, including the remaining timeout under
+//! [`DEADLINE_TIMEOUT_KEY`](crate::DEADLINE_TIMEOUT_KEY), and imports plain attachments as
+//! upstreams. Dubbo reserved keys (see [`DUBBO_RESERVED_KEYS`]) and the HTTP/2, gRPC and Triple
+//! transport headers (see [`is_transport_header`]) are never sent nor imported as transients.
+//! [`DubboPropagator::plain_allowlist`] further restricts the imported plain attachments.
+//!
+//! Examples:
+//! ```rust
+//! use std::collections::HashMap;
+//!
+//! use metainfo::{dubbo::DubboPropagator, Forward, MetaInfo, Propagator};
+//!
+//! let mut client = MetaInfo::new();
+//! client.set_persistent("TENANT", "t1");
+//! client.set_transient("user", "u1");
+//! client.set_transient("version", "1.0.0");
+//!
+//! let propagator = DubboPropagator::new().plain_transients(true);
+//! let mut attachments: HashMap<String, String> = HashMap::new();
+//! propagator.inject(&client, &mut attachments);
+//! assert_eq!(attachments["rpc-persist-tenant"], "t1");
+//! assert_eq!(attachments["user"], "u1");
+//! assert!(!attachments.contains_key("version"));
+//! ```
+
+use std::sync::Arc;
+
+use faststr::FastStr;
+
+use crate::{
+    convert::{Converter, HttpConverter},
+    Backward, Carrier, Forward, HttpPropagator, MetaInfo, Propagator, DEADLINE_TIMEOUT_KEY,
+    HTTP_PREFIX_BACKWARD, HTTP_PREFIX_PERSISTENT, HTTP_PREFIX_TRANSIENT,
+};
+
+/// Attachment keys reserved by Dubbo, compared case-insensitively.
+pub const DUBBO_RESERVED_KEYS: &[&str] = &[
+    "path",
+    "interface",
+    "version",
+    "group",
+    "dubbo",
+    "timeout",
+    "token",
+    "async",
+    "generic",
+    "application",
+    "remote.application",
+    "side",
+    "tag",
+    "dubbo.tag",
+    "id",
+    "input",
+    "output",
+    "_TO",
+];
+
+/// Check if the attachment key is reserved by Dubbo.
+#[inline]
+pub fn is_reserved(key: &str) -> bool {
+    DUBBO_RESERVED_KEYS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(key))
+}
+
+/// Headers of the HTTP/2 transport used by Triple and gRPC, compared case-insensitively.
+pub const TRANSPORT_HEADERS: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "authorization",
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-type",
+    "host",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "user-agent",
+];
+
+/// Prefixes of the transport headers, e.g. the `:path` pseudo-header or `grpc-timeout`, compared
+/// case-insensitively.
+const TRANSPORT_HEADER_PREFIXES: &[&str] = &[":", "grpc-", "tri-"];
+
+/// Check if the key is a header of the HTTP/2 transport, see [`TRANSPORT_HEADERS`].
+#[inline]
+pub fn is_transport_header(key: &str) -> bool {
+    TRANSPORT_HEADERS
+        .iter()
+        .any(|header| header.eq_ignore_ascii_case(key))
+        || TRANSPORT_HEADER_PREFIXES.iter().any(|prefix| {
+            key.as_bytes()
+                .get(..prefix.len())
+                .is_some_and(|head| head.eq_ignore_ascii_case(prefix.as_bytes()))
+        })
+}
+
+/// Check if the key can be a plain transient attachment.
+#[inline]
+fn is_plain(key: &str) -> bool {
+    !is_reserved(key) && !is_prefixed(key) && !is_transport_header(key)
+}
+
+#[inline]
+fn is_prefixed(key: &str) -> bool {
+    [
+        HTTP_PREFIX_PERSISTENT,
+        HTTP_PREFIX_TRANSIENT,
+        HTTP_PREFIX_BACKWARD,
+    ]
+    .iter()
+    .any(|prefix| key.starts_with(prefix))
+}
+
+/// A [`Propagator`] mapping [`MetaInfo`] to Dubbo attachments.
+#[derive(Debug, Default, Clone)]
+pub struct DubboPropagator {
+    plain_transients: bool,
+    plain_allowlist: Option<Arc<[FastStr]>>,
+}
+
+impl DubboPropagator {
+    /// Creates a `DubboPropagator` sending all entries with prefix.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Send the transients as plain attachments, and import plain attachments as upstreams.
+    #[inline]
+    pub fn plain_transients(mut self, plain: bool) -> Self {
+        self.plain_transients = plain;
+        self
+    }
+
+    /// Only import the given plain attachments as upstreams, compared case-insensitively.
+    ///
+    /// By default, all the plain attachments but the reserved keys and the transport headers are
+    /// imported. The remaining timeout is always imported.
+    #[inline]
+    pub fn plain_allowlist<I, K>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<FastStr>,
+    {
+        self.plain_allowlist = Some(keys.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Check if the plain attachment can be imported.
+    #[inline]
+    fn is_allowed(&self, key: &str) -> bool {
+        match &self.plain_allowlist {
+            Some(allowlist) => {
+                key == DEADLINE_TIMEOUT_KEY
+                    || allowlist
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(key))
+            }
+            None => true,
+        }
+    }
+}
+
+impl Propagator for DubboPropagator {
+    fn inject<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C) {
+        if !self.plain_transients {
+            return HttpPropagator.inject(mi, carrier);
+        }
+        if let Some(persistents) = mi.get_all_persistents() {
+            for (k, v) in persistents {
+                carrier.set_header(HttpConverter.add_persistent_prefix(k), v.clone());
+            }
+        }
+        for (k, v) in mi.iter_transients_with_deadline() {
+            if is_plain(k) {
+                carrier.set_header(FastStr::new(k), v);
+            }
+        }
+    }
+
+    fn inject_backward<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C) {
+        if let Some(transients) = mi.get_all_backward_transients() {
+            for (k, v) in transients {
+                carrier.set_header(HttpConverter.add_backward_prefix(k), v.clone());
+            }
+        }
+    }
+
+    #[inline]
+    fn extract_persistent(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+        match HttpConverter.remove_persistent_prefix(key) {
+            Some(key) => {
+                mi.import_persistent(key, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    fn extract_upstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+        if let Some(key) = HttpConverter.remove_transient_prefix(key) {
+            mi.import_upstream(key, value);
+            return true;
+        }
+        if self.plain_transients && is_plain(key) && self.is_allowed(key) {
+            mi.import_upstream(FastStr::new(key), value);
+            return true;
+        }
+        false
+    }
+
+    #[inline]
+    fn extract_backward_downstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+        match HttpConverter.remove_backward_prefix(key) {
+            Some(key) => {
+                mi.set_backward_downstream(key, value);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn attachments(kvs: &[(&str, &str)]) -> HashMap<String, String> {
+        kvs.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_prefixed() {
+        let mut client = MetaInfo::new();
+        client.set_persistent("TENANT", "t1");
+        client.set_transient("USER", "u1");
+
+        let mut carrier = HashMap::new();
+        DubboPropagator::new().inject(&client, &mut carrier);
+        assert_eq!(
+            carrier,
+            attachments(&[("rpc-persist-tenant", "t1"), ("rpc-transit-user", "u1")])
+        );
+
+        carrier.insert("path".into(), "com.example.Service".into());
+        carrier.insert("plain".into(), "p".into());
+        let server = DubboPropagator::new().extract(&carrier);
+        assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(server.get_upstream("USER").unwrap(), "u1");
+        assert!(server.get_upstream("plain").is_none());
+    }
+
+    #[test]
+    fn test_plain_transients() {
+        let propagator = DubboPropagator::new().plain_transients(true);
+        let carrier = attachments(&[
+            ("path", "com.example.Service"),
+            ("Version", "1.0.0"),
+            ("rpc-persist-tenant", "t1"),
+            ("user", "u1"),
+        ]);
+        let server = propagator.extract(&carrier);
+        assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(server.get_upstream("user").unwrap(), "u1");
+        assert!(server.get_upstream("path").is_none());
+        assert!(server.get_upstream("Version").is_none());
+        assert!(server.get_upstream("rpc-persist-tenant").is_none());
+    }
+
+    #[test]
+    fn test_triple_headers() {
+        // the request headers of a Triple call from a Java client
+        let carrier = attachments(&[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", "/com.example.Greeter/sayHello"),
+            (":authority", "localhost:50051"),
+            ("content-type", "application/grpc+proto"),
+            ("te", "trailers"),
+            ("user-agent", "dubbo/3.2.0"),
+            ("grpc-accept-encoding", "gzip"),
+            ("grpc-timeout", "3000m"),
+            ("tri-consumer-appname", "consumer"),
+            ("tri-service-version", "1.0.0"),
+            ("tri-service-group", "g1"),
+            ("rpc-persist-tenant", "t1"),
+            ("user", "u1"),
+            ("trace", "x1"),
+        ]);
+        let server = DubboPropagator::new()
+            .plain_transients(true)
+            .extract(&carrier);
+        assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+        let mut upstreams: Vec<_> = server.get_all_upstreams().unwrap().keys().collect();
+        upstreams.sort();
+        assert_eq!(upstreams, ["trace", "user"]);
+
+        let server = DubboPropagator::new()
+            .plain_transients(true)
+            .plain_allowlist(["USER"])
+            .extract(&carrier);
+        assert_eq!(server.get_upstream("user").unwrap(), "u1");
+        assert_eq!(server.get_all_upstreams().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_plain_deadline() {
+        let propagator = DubboPropagator::new().plain_transients(true);
+        let mut client = MetaInfo::new();
+        client.set_timeout(std::time::Duration::from_secs(10));
+
+        let mut carrier = HashMap::new();
+        propagator.inject(&client, &mut carrier);
+        assert!(carrier.contains_key(crate::DEADLINE_TIMEOUT_KEY));
+        let server = propagator.extract(&carrier);
+        assert!(server.deadline().is_some());
+        assert!(server.get_all_upstreams().is_none());
+    }
+
+    #[test]
+    fn test_backward() {
+        let mut server = MetaInfo::new();
+        server.set_backward_transient("COST", "10");
+
+        let mut carrier = HashMap::new();
+        DubboPropagator::new().inject_backward(&server, &mut carrier);
+        assert_eq!(carrier, attachments(&[("rpc-backward-cost", "10")]));
+
+        let mut client = MetaInfo::new();
+        DubboPropagator::new().extract_backward_into(&carrier, &mut client);
+        assert_eq!(client.get_backward_downstream("COST").unwrap(), "10");
+    }
+}
@@ -0,0 +1,212 @@
+//! Bootstrap of a root [`MetaInfo`] from environment variables and command line arguments.
+//!
+//! Processes without an incoming request, e.g. batch jobs, can seed their context from variables
+//! named with the rpc prefix, e.g. `RPC_PERSIST_TENANT=t1` or `RPC_TRANSIT_USER=u1`, or from
+//! arguments like `--meta RPC_PERSIST_TENANT=t1`. Since such a process is the origin of its
+//! requests, transients are set as transients to be sent to the next hop, and
+//! `RPC_TRANSIT_METAINFO_DEADLINE_MS` sets the deadline.
+//!
+//! A process spawned with the context of its parent can instead consider the parent as its
+//! upstream, see [`EnvTransients`].
+//!
+//! [`MetaInfo::env_vars`] is the inverse, to export the context to a spawned process.
+
+use std::ffi::OsString;
+
+use faststr::FastStr;
+
+use crate::{
+    convert::{Converter, RpcConverter},
+    Forward, MetaInfo, DEADLINE_SKEW_MARGIN, DEADLINE_TIMEOUT_KEY,
+};
+
+const META_ARG: &str = "--meta";
+
+/// How the transients read from the environment are set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnvTransients {
+    /// Set as transients, to be sent to the next hop.
+    ///
+    /// This is for the processes at the origin of their requests.
+    #[default]
+    Transient,
+    /// Set as upstreams, i.e. received from the previous hop.
+    ///
+    /// This is for the processes spawned by a parent considered as their upstream.
+    Upstream,
+}
+
+/// The environment variables of the current process, skipping non unicode names or values.
+#[inline]
+pub(crate) fn unicode_vars() -> impl Iterator<Item = (String, String)> {
+    std::env::vars_os().filter_map(|(k, v)| {
+        match (OsString::into_string(k), OsString::into_string(v)) {
+            (Ok(k), Ok(v)) => Some((k, v)),
+            _ => None,
+        }
+    })
+}
+
+impl MetaInfo {
+    /// Creates a `MetaInfo` from the environment variables of the current process.
+    ///
+    /// Variables with non unicode names or values are skipped.
+    #[inline]
+    pub fn from_env() -> MetaInfo {
+        Self::from_env_vars(unicode_vars())
+    }
+
+    /// Creates a `MetaInfo` from k-v pairs in the environment variable format.
+    ///
+    /// Pairs without rpc prefix are skipped.
+    #[inline]
+    pub fn from_env_vars<I, K, V>(vars: I) -> MetaInfo
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<FastStr>,
+    {
+        Self::from_env_vars_with(vars, EnvTransients::Transient)
+    }
+
+    /// Creates a `MetaInfo` from k-v pairs in the environment variable format, setting the
+    /// transients as specified by `transients`.
+    ///
+    /// Pairs without rpc prefix are skipped.
+    pub fn from_env_vars_with<I, K, V>(vars: I, transients: EnvTransients) -> MetaInfo
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<FastStr>,
+    {
+        let mut mi = MetaInfo::new();
+        for (k, v) in vars {
+            mi.set_from_env_var(k.as_ref(), v.into(), transients);
+        }
+        mi
+    }
+
+    /// Creates a `MetaInfo` from `--meta KEY=VALUE` or `--meta=KEY=VALUE` arguments, where `KEY`
+    /// is in the environment variable format.
+    ///
+    /// Other arguments, and keys without rpc prefix, are skipped.
+    pub fn from_args<I, S>(args: I) -> MetaInfo
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut mi = MetaInfo::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.as_ref();
+            let kv = if arg == META_ARG {
+                match args.next() {
+                    Some(kv) => FastStr::new(kv.as_ref()),
+                    None => break,
+                }
+            } else if let Some(kv) = arg.strip_prefix(META_ARG).and_then(|a| a.strip_prefix('=')) {
+                FastStr::new(kv)
+            } else {
+                continue;
+            };
+            if let Some((k, v)) = kv.split_once('=') {
+                mi.set_from_env_var(k, kv.slice_ref(v), EnvTransients::Transient);
+            }
+        }
+        mi
+    }
+
+    /// Get the forward persistents and transients as environment variables with rpc prefix,
+    /// including the remaining timeout.
+    pub fn env_vars(&self) -> Vec<(FastStr, FastStr)> {
+        self.iter_forward_with_deadline(RpcConverter).collect()
+    }
+
+    #[inline]
+    fn set_from_env_var(&mut self, key: &str, value: FastStr, transients: EnvTransients) {
+        if let Some(key) = RpcConverter.remove_persistent_prefix(key) {
+            self.set_persistent(key, value);
+        } else if let Some(key) = RpcConverter.remove_transient_prefix(key) {
+            match transients {
+                EnvTransients::Transient => {
+                    if key != DEADLINE_TIMEOUT_KEY
+                        || !self.set_deadline_from_timeout_ms(&value, DEADLINE_SKEW_MARGIN)
+                    {
+                        self.set_transient(key, value);
+                    }
+                }
+                EnvTransients::Upstream => self.import_upstream(key, value),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_from_env_vars() {
+        let mi = MetaInfo::from_env_vars([
+            ("RPC_PERSIST_TENANT", "t1"),
+            ("RPC_TRANSIT_USER", "u1"),
+            ("RPC_TRANSIT_METAINFO_DEADLINE_MS", "10000"),
+            ("PATH", "/bin"),
+        ]);
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(mi.get_transient("USER").unwrap(), "u1");
+        assert!(mi.get_transient(DEADLINE_TIMEOUT_KEY).is_none());
+        assert!(mi.remaining().unwrap() > Duration::from_secs(9));
+        assert_eq!(mi.get_all_persistents().unwrap().len(), 1);
+
+        let mi = MetaInfo::from_env_vars_with(
+            [
+                ("RPC_TRANSIT_USER", "u1"),
+                ("RPC_TRANSIT_METAINFO_DEADLINE_MS", "10000"),
+            ],
+            EnvTransients::Upstream,
+        );
+        assert_eq!(mi.get_upstream("USER").unwrap(), "u1");
+        assert!(mi.get_transient("USER").is_none());
+        assert!(mi.remaining().unwrap() > Duration::from_secs(9));
+    }
+
+    #[test]
+    fn test_from_args() {
+        let mi = MetaInfo::from_args([
+            "job",
+            "--meta",
+            "RPC_PERSIST_TENANT=t1",
+            "--meta=RPC_TRANSIT_QUERY=a=b",
+            "--meta=TENANT=t2",
+            "--verbose",
+            "--meta",
+        ]);
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(mi.get_transient("QUERY").unwrap(), "a=b");
+        assert_eq!(mi.get_all_persistents().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TENANT", "t1");
+        mi.set_transient("USER", "u1");
+        mi.set_upstream("CALLER", "c1");
+        mi.set_timeout(Duration::from_secs(10));
+
+        let mut vars = mi.env_vars();
+        vars.sort();
+        assert_eq!(vars.len(), 3);
+        assert_eq!(vars[0].0, "RPC_PERSIST_TENANT");
+        assert_eq!(vars[1].0, "RPC_TRANSIT_METAINFO_DEADLINE_MS");
+        assert_eq!(vars[2], ("RPC_TRANSIT_USER".into(), "u1".into()));
+
+        let child = MetaInfo::from_env_vars(vars);
+        assert_eq!(child.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(child.get_transient("USER").unwrap(), "u1");
+        assert!(child.deadline().is_some());
+    }
+}
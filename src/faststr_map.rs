@@ -3,12 +3,18 @@ use std::{any::TypeId, collections::hash_map::Entry};
 use faststr::FastStr;
 use rustc_hash::FxHashMapRand;
 
+use crate::{
+    heap::{self, HeapSize},
+    type_map::TypeNames,
+};
+
 /// This is an optimized version of TypeMap to FastStr that eliminates the need to Box the values.
 ///
 /// This map is suitable for T that impls both From<FastStr> and Into<FastStr>.
 #[derive(Debug, Default)]
 pub struct FastStrMap {
     inner: FxHashMapRand<TypeId, FastStr>,
+    names: TypeNames,
 }
 
 impl FastStrMap {
@@ -16,6 +22,7 @@ impl FastStrMap {
     pub fn new() -> Self {
         Self {
             inner: FxHashMapRand::default(),
+            names: TypeNames::default(),
         }
     }
 
@@ -23,11 +30,13 @@ impl FastStrMap {
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             inner: FxHashMapRand::with_capacity_and_hasher(capacity, Default::default()),
+            names: TypeNames::default(),
         }
     }
 
     #[inline]
     pub fn insert<T: Send + Sync + 'static>(&mut self, t: FastStr) {
+        self.names.record::<T>();
         self.inner.insert(TypeId::of::<T>(), t);
     }
 
@@ -54,11 +63,18 @@ impl FastStrMap {
     #[inline]
     pub fn clear(&mut self) {
         self.inner.clear();
+        self.names.clear();
     }
 
     #[inline]
     pub fn extend(&mut self, other: FastStrMap) {
-        self.inner.extend(other.inner)
+        self.inner.extend(other.inner);
+        self.names.extend(other.names);
+    }
+
+    #[inline]
+    pub(crate) fn get_by_id(&self, id: &TypeId) -> Option<&FastStr> {
+        self.inner.get(id)
     }
 
     #[inline]
@@ -68,9 +84,35 @@ impl FastStrMap {
 
     #[inline]
     pub fn entry<T: 'static>(&mut self) -> Entry<'_, TypeId, FastStr> {
+        self.names.record::<T>();
         self.inner.entry(TypeId::of::<T>())
     }
 
+    /// Retain only the entries for which `f` returns true, given the type id, the type name and
+    /// the value, which `f` may replace.
+    #[inline]
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&TypeId, &FastStr, &mut FastStr) -> bool) {
+        let names = &self.names;
+        self.inner.retain(|id, v| f(id, &names.get(id), v))
+    }
+
+    /// Iterate over the entries with their type names.
+    #[inline]
+    pub(crate) fn named_iter(&self) -> impl Iterator<Item = (FastStr, &FastStr)> + '_ {
+        self.inner.iter().map(|(id, v)| (self.names.get(id), v))
+    }
+
+    /// Iterate over the type names of the entries.
+    #[inline]
+    pub(crate) fn type_names(&self) -> impl Iterator<Item = FastStr> + '_ {
+        self.inner.keys().map(|id| self.names.get(id))
+    }
+
+    /// Get the approximate size of the heap allocations of the map and its values.
+    pub(crate) fn approx_heap_size(&self) -> usize {
+        heap::map_size(&self.inner) + self.inner.values().map(HeapSize::heap_size).sum::<usize>()
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
@@ -18,9 +18,17 @@ pub trait Forward {
         &self,
     ) -> Option<AHashMap<FastStr, FastStr>>;
 
+    /// Iterate over the persistents and transients with rpc prefix.
+    ///
+    /// The remaining timeout of the deadline is not included, see
+    /// [`MetaInfo::deadline_header_with_rpc_prefix`](crate::MetaInfo::deadline_header_with_rpc_prefix).
     fn iter_persistents_and_transients_with_rpc_prefix(
         &self,
     ) -> impl Iterator<Item = (FastStr, &FastStr)>;
+    /// Iterate over the persistents and transients with http prefix.
+    ///
+    /// The remaining timeout of the deadline is not included, see
+    /// [`MetaInfo::deadline_header_with_http_prefix`](crate::MetaInfo::deadline_header_with_http_prefix).
     fn iter_persistents_and_transients_with_http_prefix(
         &self,
     ) -> impl Iterator<Item = (FastStr, &FastStr)>;
@@ -0,0 +1,218 @@
+//! gRPC metadata integration for `tonic`.
+//!
+//! Entries are mapped to metadata keys in the http format, e.g. the persistent `TEST_KEY` is sent
+//! as `rpc-persist-test-key`. Values which are not valid ascii metadata values are sent as binary
+//! metadata under the key suffixed with `-bin`, e.g. `rpc-persist-test-key-bin`.
+//!
+//! Keys which are not valid metadata keys are skipped.
+//!
+//! Backward entries are carried by the response metadata, or the trailers of a [`Status`] when
+//! the call fails.
+
+use std::sync::Arc;
+
+use faststr::FastStr;
+use tonic::{
+    metadata::{
+        AsciiMetadataKey, AsciiMetadataValue, BinaryMetadataKey, BinaryMetadataValue,
+        KeyAndValueRef, MetadataMap,
+    },
+    service::Interceptor,
+    Request, Status,
+};
+
+use crate::{
+    convert::{Converter, HttpConverter},
+    propagation::{extract_backward_entry, extract_entry},
+    Backward, HttpPropagator, MetaInfo,
+};
+
+const BINARY_SUFFIX: &str = "-bin";
+
+#[inline]
+fn insert(metadata: &mut MetadataMap, key: &str, value: &str) {
+    // ascii metadata values only allow visible ascii characters and spaces
+    if value.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        if let (Ok(k), Ok(v)) = (
+            AsciiMetadataKey::from_bytes(key.as_bytes()),
+            AsciiMetadataValue::try_from(value),
+        ) {
+            metadata.insert(k, v);
+            return;
+        }
+    }
+    let mut bin_key = String::with_capacity(key.len() + BINARY_SUFFIX.len());
+    bin_key.push_str(key);
+    bin_key.push_str(BINARY_SUFFIX);
+    if let Ok(k) = BinaryMetadataKey::from_bytes(bin_key.as_bytes()) {
+        metadata.insert_bin(k, BinaryMetadataValue::from_bytes(value.as_bytes()));
+    }
+}
+
+#[inline]
+fn for_each(metadata: &MetadataMap, mut f: impl FnMut(&str, FastStr)) {
+    for kv in metadata.iter() {
+        match kv {
+            KeyAndValueRef::Ascii(k, v) => {
+                if let Ok(v) = v.to_str() {
+                    f(k.as_str(), FastStr::new(v));
+                }
+            }
+            KeyAndValueRef::Binary(k, v) => {
+                let k = k.as_str();
+                let k = k.strip_suffix(BINARY_SUFFIX).unwrap_or(k);
+                if let Some(v) = v
+                    .to_bytes()
+                    .ok()
+                    .and_then(|v| String::from_utf8(v.to_vec()).ok())
+                {
+                    f(k, FastStr::from_string(v));
+                }
+            }
+        }
+    }
+}
+
+/// Insert the forward persistents and transients of a `MetaInfo` into gRPC metadata.
+///
+/// This is used by clients on the request metadata.
+pub fn inject(mi: &MetaInfo, metadata: &mut MetadataMap) {
+    for (k, v) in mi.iter_forward_with_deadline(HttpConverter) {
+        insert(metadata, &k, &v);
+    }
+}
+
+/// Extract the forward persistents and transients from gRPC metadata into a `MetaInfo`.
+///
+/// This is used by servers on the request metadata, transients are set as upstreams.
+pub fn extract(metadata: &MetadataMap, mi: &mut MetaInfo) {
+    for_each(metadata, |k, v| extract_entry(&HttpPropagator, mi, k, v));
+}
+
+/// Insert the backward transients of a `MetaInfo` into gRPC metadata.
+///
+/// This is used by servers on the response metadata or the [`Status`] metadata.
+pub fn inject_backward(mi: &MetaInfo, metadata: &mut MetadataMap) {
+    if let Some(transients) = mi.get_all_backward_transients() {
+        for (k, v) in transients {
+            insert(metadata, &HttpConverter.add_backward_prefix(k), v);
+        }
+    }
+}
+
+/// Extract the backward transients from gRPC metadata into a `MetaInfo` as downstreams.
+///
+/// This is used by clients on the response metadata or the [`Status`] metadata.
+pub fn extract_backward(metadata: &MetadataMap, mi: &mut MetaInfo) {
+    for_each(metadata, |k, v| {
+        extract_backward_entry(&HttpPropagator, mi, k, v)
+    });
+}
+
+/// An [`Interceptor`] injecting the task local [`METAINFO`](crate::METAINFO) into client requests.
+///
+/// Requests sent outside of a `METAINFO` scope, or while it is mutably borrowed, are left
+/// untouched.
+#[cfg(feature = "task_local")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClientInterceptor;
+
+#[cfg(feature = "task_local")]
+impl Interceptor for ClientInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let _ = crate::METAINFO.try_with(|mi| {
+            if let Ok(mi) = mi.try_borrow() {
+                inject(&mi, request.metadata_mut());
+            }
+        });
+        Ok(request)
+    }
+}
+
+/// An [`Interceptor`] extracting a `MetaInfo` from server requests.
+///
+/// The `MetaInfo` is inserted into the request extensions as an `Arc<MetaInfo>`, handlers can
+/// derive from it with [`MetaInfo::from`] to enter the `METAINFO` scope.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ServerInterceptor;
+
+impl Interceptor for ServerInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let mut mi = MetaInfo::new();
+        extract(request.metadata(), &mut mi);
+        request.extensions_mut().insert(Arc::new(mi));
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Forward;
+
+    #[test]
+    fn test_forward() {
+        let mut client = MetaInfo::new();
+        client.set_persistent("TEST_KEY", "persist");
+        client.set_transient("TEST_KEY", "transit");
+        client.set_persistent("NON_ASCII", "值");
+
+        let mut metadata = MetadataMap::new();
+        inject(&client, &mut metadata);
+        assert_eq!(metadata.get("rpc-persist-test-key").unwrap(), "persist");
+        assert_eq!(metadata.get("rpc-transit-test-key").unwrap(), "transit");
+        assert!(metadata.get_bin("rpc-persist-non-ascii-bin").is_some());
+
+        let mut request = Request::from_parts(metadata, Default::default(), ());
+        request = ServerInterceptor.call(request).unwrap();
+        let server = request.extensions().get::<Arc<MetaInfo>>().unwrap();
+        assert_eq!(server.get_persistent("TEST_KEY").unwrap(), "persist");
+        assert_eq!(server.get_upstream("TEST_KEY").unwrap(), "transit");
+        assert_eq!(server.get_persistent("NON_ASCII").unwrap(), "值");
+    }
+
+    #[test]
+    fn test_backward() {
+        let mut server = MetaInfo::new();
+        server.set_backward_transient("TEST_KEY", "backward");
+
+        let mut status = Status::internal("error");
+        inject_backward(&server, status.metadata_mut());
+        assert_eq!(
+            status.metadata().get("rpc-backward-test-key").unwrap(),
+            "backward"
+        );
+
+        let mut client = MetaInfo::new();
+        extract_backward(status.metadata(), &mut client);
+        assert_eq!(
+            client.get_backward_downstream("TEST_KEY").unwrap(),
+            "backward"
+        );
+    }
+
+    #[cfg(feature = "task_local")]
+    #[test]
+    fn test_client_interceptor() {
+        use std::cell::RefCell;
+
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TEST_KEY", "persist");
+        let (request, borrowed) = crate::METAINFO.sync_scope(RefCell::new(mi), || {
+            let request = ClientInterceptor.call(Request::new(())).unwrap();
+            let borrowed = crate::METAINFO.with(|mi| {
+                let _guard = mi.borrow_mut();
+                ClientInterceptor.call(Request::new(())).unwrap()
+            });
+            (request, borrowed)
+        });
+        assert_eq!(
+            request.metadata().get("rpc-persist-test-key").unwrap(),
+            "persist"
+        );
+        assert!(borrowed.metadata().is_empty());
+
+        let request = ClientInterceptor.call(Request::new(())).unwrap();
+        assert!(request.metadata().is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+//! Approximate memory accounting of [`MetaInfo`].
+//!
+//! The sizes are estimated from the capacities of the maps and the lengths of the strings, they
+//! don't account for the allocator overhead, nor for strings shared between several maps. Typed
+//! values are counted by their boxed size, plus their own heap allocations if they are inserted
+//! with [`MetaInfo::insert_sized`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    mem::size_of,
+    panic::Location,
+};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+
+use crate::{MetaInfo, MutationKind, Section, TypeMap, DEFAULT_MAP_SIZE};
+
+// strings up to this length are stored inline by `FastStr`
+const FASTSTR_INLINE_CAP: usize = 24;
+
+/// Values which can report the size of their heap allocations.
+pub trait HeapSize {
+    /// The size of the heap allocations owned by the value, excluding the value itself.
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! heap_size_zero {
+    ($($t:ty),*) => {
+        $(
+            impl HeapSize for $t {
+                #[inline]
+                fn heap_size(&self) -> usize {
+                    0
+                }
+            }
+        )*
+    };
+}
+
+heap_size_zero!(
+    bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+
+impl HeapSize for String {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for FastStr {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        if self.len() > FASTSTR_INLINE_CAP {
+            self.len()
+        } else {
+            0
+        }
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<T> {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        size_of::<T>() + self.as_ref().heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, T::heap_size)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(T::heap_size).sum::<usize>()
+    }
+}
+
+/// The approximate size of the table of a hash map, one control byte per bucket.
+#[inline]
+pub(crate) fn map_size<K, V, S>(map: &HashMap<K, V, S>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+/// The approximate size of a string map and its entries.
+#[inline]
+pub(crate) fn str_map_size(map: &AHashMap<FastStr, FastStr>) -> usize {
+    map_size(map)
+        + map
+            .iter()
+            .map(|(k, v)| k.heap_size() + v.heap_size())
+            .sum::<usize>()
+}
+
+/// The approximate heap size of a [`MetaInfo`], returned by [`MetaInfo::approx_heap_size`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HeapSizeReport {
+    /// The size of the entries in the current scope.
+    pub scope: usize,
+    /// The size of the whole scope chain, including the current scope and the parents.
+    pub chain: usize,
+}
+
+impl MetaInfo {
+    /// Insert a type into this `MetaInfo`, whose heap allocations are accounted by
+    /// [`MetaInfo::approx_heap_size`].
+    #[inline]
+    #[track_caller]
+    pub fn insert_sized<T: HeapSize + Send + Sync + 'static>(&mut self, val: T) {
+        if !self.observe_type::<T>(
+            MutationKind::Insert,
+            Section::Typed,
+            None,
+            Location::caller(),
+        ) {
+            return;
+        }
+        self.tmap
+            .get_or_insert_with(|| TypeMap::with_capacity(DEFAULT_MAP_SIZE))
+            .insert_sized(val);
+    }
+
+    /// The approximate heap size of the current scope.
+    fn scope_heap_size(&self) -> usize {
+        self.tmap.as_ref().map_or(0, TypeMap::approx_heap_size)
+            + self
+                .faststr_tmap
+                .as_ref()
+                .map_or(0, |faststr_tmap| faststr_tmap.approx_heap_size())
+            + self.smap.as_ref().map_or(0, str_map_size)
+            + self
+                .forward_node
+                .as_ref()
+                .map_or(0, |node| node.approx_heap_size())
+            + self
+                .backward_node
+                .as_ref()
+                .map_or(0, |node| node.approx_heap_size())
+    }
+
+    /// Get the approximate heap size of this `MetaInfo`.
+    ///
+    /// The parents are counted with their `Arc` allocation. Observers and the audit journal are
+    /// not counted.
+    ///
+    /// Examples:
+    /// ```rust
+    /// use metainfo::MetaInfo;
+    ///
+    /// let mut mi = MetaInfo::new();
+    /// mi.insert_sized(vec![0u8; 1024]);
+    /// let (_, child) = mi.derive();
+    ///
+    /// let size = child.approx_heap_size();
+    /// assert_eq!(size.scope, 0);
+    /// assert!(size.chain > 1024);
+    /// ```
+    pub fn approx_heap_size(&self) -> HeapSizeReport {
+        let scope = self.scope_heap_size();
+        let mut chain = scope;
+        let mut cur = self.parent.as_deref();
+        while let Some(parent) = cur {
+            chain += arc_size() + parent.scope_heap_size();
+            cur = parent.parent.as_deref();
+        }
+        HeapSizeReport { scope, chain }
+    }
+
+    /// Get the approximate heap size of the scope trees of the given `MetaInfo`s.
+    ///
+    /// The ancestors shared by several `MetaInfo`s, i.e. the same `Arc`, are only counted once.
+    pub fn approx_tree_heap_size<'a, I>(leaves: I) -> usize
+    where
+        I: IntoIterator<Item = &'a MetaInfo>,
+    {
+        let mut seen = HashSet::new();
+        let mut total = 0;
+        for leaf in leaves {
+            let mut cur = leaf;
+            let mut is_leaf = true;
+            while seen.insert(cur as *const MetaInfo) {
+                total += cur.scope_heap_size();
+                if !is_leaf {
+                    total += arc_size();
+                }
+                let Some(parent) = cur.parent.as_deref() else {
+                    break;
+                };
+                cur = parent;
+                is_leaf = false;
+            }
+        }
+        total
+    }
+}
+
+/// The size of the `Arc` allocation of a parent, with its two counters.
+#[inline]
+fn arc_size() -> usize {
+    size_of::<MetaInfo>() + 2 * size_of::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::Forward;
+
+    #[test]
+    fn test_approx_heap_size() {
+        let empty = MetaInfo::new();
+        assert_eq!(empty.approx_heap_size(), HeapSizeReport::default());
+
+        let mut mi = MetaInfo::new();
+        mi.insert(1u64);
+        let unsized_size = mi.approx_heap_size().scope;
+        mi.insert_sized(String::from("x").repeat(1000));
+        assert!(mi.approx_heap_size().scope >= unsized_size + 1000);
+
+        let before = mi.approx_heap_size().scope;
+        mi.set_persistent("K", "v".repeat(100));
+        assert!(mi.approx_heap_size().scope > before + 100);
+
+        let report = mi.approx_heap_size();
+        assert_eq!(report.scope, report.chain);
+        let child = MetaInfo::from(Arc::new(mi));
+        let report = child.approx_heap_size();
+        // the forward node is copied into the child
+        assert!(report.scope > 100);
+        assert!(report.chain > report.scope + 1000);
+    }
+
+    #[test]
+    fn test_shared_parents() {
+        let mut root = MetaInfo::new();
+        root.insert_string("k".into(), "v".repeat(1000).into());
+        let root = Arc::new(root);
+        let a = MetaInfo::from(root.clone());
+        let b = MetaInfo::from(root.clone());
+
+        let chain = a.approx_heap_size().chain;
+        assert!(chain > 1000);
+        assert_eq!(MetaInfo::approx_tree_heap_size([&a]), chain);
+        assert_eq!(
+            MetaInfo::approx_tree_heap_size([&a, &b]),
+            chain * 2 - root.approx_heap_size().chain - arc_size()
+        );
+        assert!(MetaInfo::approx_tree_heap_size([&a, &b]) < chain * 2);
+    }
+}
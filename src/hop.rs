@@ -0,0 +1,209 @@
+//! Hop counting and loop detection through [`MetaInfo`].
+//!
+//! Hop tracking is opt-in: the origin of a request calls [`MetaInfo::start_hop_tracking`], which
+//! sets the persistent key [`HOP_COUNT_KEY`]. Each time the key is imported with
+//! [`MetaInfo::import_persistent`], as done by the [`Propagator`](crate::Propagator)s and
+//! `strip_{rpc,http}_prefix_and_set_persistent`, the hop count is incremented by one.
+//!
+//! Servers call [`MetaInfo::enter_service`] to check the hop count against a maximum and to append
+//! themselves to the visited-service trail stored in the persistent key [`HOP_TRAIL_KEY`].
+
+use std::{error::Error, fmt};
+
+use faststr::FastStr;
+
+use crate::{Forward, MetaInfo};
+
+/// The persistent key used to transport the hop count.
+pub const HOP_COUNT_KEY: &str = "METAINFO_HOP_COUNT";
+
+/// The persistent key used to transport the visited-service trail.
+pub const HOP_TRAIL_KEY: &str = "METAINFO_HOP_TRAIL";
+
+const HOP_TRAIL_SEPARATOR: char = ',';
+
+/// The error returned by [`MetaInfo::enter_service`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HopError {
+    /// The hop count exceeds the configured maximum.
+    TooManyHops { hops: u32, max_hops: u32 },
+    /// The hop count received from the upstream is not a number.
+    InvalidHopCount { value: FastStr },
+    /// The service name contains the trail separator `,`.
+    InvalidService { service: FastStr },
+}
+
+impl fmt::Display for HopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HopError::TooManyHops { hops, max_hops } => {
+                write!(f, "hop count {hops} exceeds the maximum {max_hops}")
+            }
+            HopError::InvalidHopCount { value } => write!(f, "invalid hop count {value:?}"),
+            HopError::InvalidService { service } => {
+                write!(
+                    f,
+                    "service name {service:?} contains {HOP_TRAIL_SEPARATOR:?}"
+                )
+            }
+        }
+    }
+}
+
+impl Error for HopError {}
+
+impl MetaInfo {
+    /// Start hop tracking at the origin of a request.
+    #[inline]
+    #[track_caller]
+    pub fn start_hop_tracking(&mut self) {
+        self.set_persistent(HOP_COUNT_KEY, "0");
+    }
+
+    /// Get the number of hops the request has gone through, `None` if hop tracking is not
+    /// started.
+    #[inline]
+    pub fn hop_count(&self) -> Option<u32> {
+        self.get_persistent(HOP_COUNT_KEY)
+            .and_then(|count| count.parse().ok())
+    }
+
+    /// Get the services the request has visited, in order.
+    #[inline]
+    pub fn hop_trail(&self) -> Vec<FastStr> {
+        match self.get_persistent(HOP_TRAIL_KEY) {
+            Some(trail) => trail
+                .split(HOP_TRAIL_SEPARATOR)
+                .filter(|s| !s.is_empty())
+                .map(|s| trail.slice_ref(s))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the first service that appears twice in the visited-service trail.
+    #[inline]
+    pub fn hop_cycle(&self) -> Option<FastStr> {
+        let trail = self.hop_trail();
+        trail
+            .iter()
+            .enumerate()
+            .find(|(i, s)| trail[..*i].contains(s))
+            .map(|(_, s)| s.clone())
+    }
+
+    /// Check the hop count against `max_hops` and append `service` to the visited-service trail.
+    ///
+    /// Does nothing if hop tracking is not started. Fails if the hop count is not a number, so a
+    /// corrupted hop count can't disable the loop detection, or if the service name contains `,`.
+    #[track_caller]
+    pub fn enter_service(&mut self, service: &str, max_hops: u32) -> Result<(), HopError> {
+        if service.contains(HOP_TRAIL_SEPARATOR) {
+            return Err(HopError::InvalidService {
+                service: FastStr::new(service),
+            });
+        }
+        let Some(value) = self.get_persistent(HOP_COUNT_KEY) else {
+            return Ok(());
+        };
+        let Ok(hops) = value.parse::<u32>() else {
+            return Err(HopError::InvalidHopCount { value });
+        };
+        if hops > max_hops {
+            return Err(HopError::TooManyHops { hops, max_hops });
+        }
+        let trail = match self.get_persistent(HOP_TRAIL_KEY) {
+            Some(trail) if !trail.is_empty() => {
+                let mut s = String::with_capacity(trail.len() + 1 + service.len());
+                s.push_str(&trail);
+                s.push(HOP_TRAIL_SEPARATOR);
+                s.push_str(service);
+                FastStr::from_string(s)
+            }
+            _ => FastStr::new(service),
+        };
+        self.set_persistent(HOP_TRAIL_KEY, trail);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transit(mi: &MetaInfo) -> MetaInfo {
+        let mut next = MetaInfo::new();
+        for (k, v) in mi.iter_persistents_and_transients_with_rpc_prefix() {
+            next.strip_rpc_prefix_and_set_persistent(k, v.clone());
+        }
+        next
+    }
+
+    #[test]
+    fn test_hop_count() {
+        let mut mi = MetaInfo::new();
+        assert!(mi.hop_count().is_none());
+        assert!(mi.enter_service("a", 0).is_ok());
+        assert!(mi.hop_trail().is_empty());
+
+        mi.start_hop_tracking();
+        mi.enter_service("a", 2).unwrap();
+        let mut mi = transit(&mi);
+        assert_eq!(mi.hop_count(), Some(1));
+        mi.enter_service("b", 2).unwrap();
+        let mut mi = transit(&mi);
+        mi.enter_service("c", 2).unwrap();
+        let mut mi = transit(&mi);
+        assert_eq!(mi.hop_count(), Some(3));
+        assert_eq!(
+            mi.enter_service("d", 2),
+            Err(HopError::TooManyHops {
+                hops: 3,
+                max_hops: 2
+            })
+        );
+        assert_eq!(mi.hop_trail(), ["a", "b", "c"]);
+        assert!(mi.hop_cycle().is_none());
+    }
+
+    #[test]
+    fn test_hop_cycle() {
+        let mut mi = MetaInfo::new();
+        mi.start_hop_tracking();
+        for service in ["a", "b", "a", "b"] {
+            mi.enter_service(service, 10).unwrap();
+            mi = transit(&mi);
+        }
+        assert_eq!(mi.hop_count(), Some(4));
+        assert_eq!(mi.hop_cycle().unwrap(), "a");
+
+        let mut server = MetaInfo::new();
+        server.strip_http_prefix_and_set_persistent("rpc-persist-metainfo-hop-count", "4");
+        assert_eq!(server.hop_count(), Some(5));
+    }
+
+    #[test]
+    fn test_invalid_hops() {
+        let mut mi = MetaInfo::new();
+        mi.strip_rpc_prefix_and_set_persistent("RPC_PERSIST_METAINFO_HOP_COUNT", "garbage");
+        assert!(mi.hop_count().is_none());
+        assert_eq!(
+            mi.enter_service("a", 10),
+            Err(HopError::InvalidHopCount {
+                value: "garbage".into()
+            })
+        );
+
+        // an application key which happens to be named like the old key is left alone
+        mi.strip_rpc_prefix_and_set_persistent("RPC_PERSIST_HOP_COUNT", "1");
+        assert_eq!(mi.get_persistent("HOP_COUNT").unwrap(), "1");
+
+        let mut mi = MetaInfo::new();
+        mi.start_hop_tracking();
+        assert!(matches!(
+            mi.enter_service("a,b", 10),
+            Err(HopError::InvalidService { .. })
+        ));
+        assert!(mi.hop_trail().is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+//! JSON import and export of [`MetaInfo`], for debugging and test fixtures.
+//!
+//! The schema groups the string entries by section, keys are sorted so the output is stable:
+//! ```json
+//! {
+//!   "strings": { "key": "value" },
+//!   "persistent": { "TENANT": "t1" },
+//!   "transient": {},
+//!   "upstream": {},
+//!   "backward_transient": {},
+//!   "backward_downstream": {},
+//!   "types": ["my_crate::RequestId"]
+//! }
+//! ```
+//!
+//! `strings` and `types` include the entries inherited from the parents. `types` lists the type
+//! names of the typed entries, it's informative only and ignored by [`MetaInfo::from_json`].
+//! Missing sections are treated as empty, unknown sections are rejected.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+use serde::{Deserialize, Serialize};
+
+use crate::{Backward, Forward, MetaInfo, DEFAULT_MAP_SIZE};
+
+#[derive(Serialize)]
+struct Export<'a> {
+    strings: BTreeMap<&'a str, &'a str>,
+    persistent: BTreeMap<&'a str, &'a str>,
+    transient: BTreeMap<&'a str, &'a str>,
+    upstream: BTreeMap<&'a str, &'a str>,
+    backward_transient: BTreeMap<&'a str, &'a str>,
+    backward_downstream: BTreeMap<&'a str, &'a str>,
+    types: BTreeSet<&'a str>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Import {
+    #[serde(default)]
+    strings: BTreeMap<String, String>,
+    #[serde(default)]
+    persistent: BTreeMap<String, String>,
+    #[serde(default)]
+    transient: BTreeMap<String, String>,
+    #[serde(default)]
+    upstream: BTreeMap<String, String>,
+    #[serde(default)]
+    backward_transient: BTreeMap<String, String>,
+    #[serde(default)]
+    backward_downstream: BTreeMap<String, String>,
+    #[serde(default, rename = "types")]
+    _types: Vec<String>,
+}
+
+#[inline]
+fn sorted(map: Option<&AHashMap<FastStr, FastStr>>) -> BTreeMap<&str, &str> {
+    map.into_iter()
+        .flatten()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect()
+}
+
+#[inline]
+fn entries(map: BTreeMap<String, String>) -> impl Iterator<Item = (FastStr, FastStr)> {
+    map.into_iter()
+        .map(|(k, v)| (FastStr::from_string(k), FastStr::from_string(v)))
+}
+
+impl MetaInfo {
+    /// Export self as pretty-printed JSON.
+    ///
+    /// See the [module docs](crate::json) for the schema.
+    pub fn to_json(&self) -> String {
+        let strings = self.flatten_strings();
+        let (mut names, faststr_names) = self.type_names();
+        names.extend(faststr_names);
+
+        let export = Export {
+            strings: sorted(Some(&strings)),
+            persistent: sorted(self.get_all_persistents()),
+            transient: sorted(self.get_all_transients()),
+            upstream: sorted(self.get_all_upstreams()),
+            backward_transient: sorted(self.get_all_backward_transients()),
+            backward_downstream: sorted(self.get_all_backward_downstreams()),
+            types: names.iter().map(|name| name.as_str()).collect(),
+        };
+        // maps of strings can always be serialized
+        serde_json::to_string_pretty(&export).unwrap()
+    }
+
+    /// Import a `MetaInfo` from JSON exported by [`MetaInfo::to_json`].
+    pub fn from_json(json: &str) -> Result<MetaInfo, serde_json::Error> {
+        let import: Import = serde_json::from_str(json)?;
+        let mut mi = MetaInfo::new();
+        if !import.strings.is_empty() {
+            let mut smap = AHashMap::with_capacity(DEFAULT_MAP_SIZE.max(import.strings.len()));
+            smap.extend(entries(import.strings));
+            mi.smap = Some(smap);
+        }
+        for (k, v) in entries(import.persistent) {
+            mi.set_persistent(k, v);
+        }
+        for (k, v) in entries(import.transient) {
+            mi.set_transient(k, v);
+        }
+        for (k, v) in entries(import.upstream) {
+            mi.set_upstream(k, v);
+        }
+        for (k, v) in entries(import.backward_transient) {
+            mi.set_backward_transient(k, v);
+        }
+        for (k, v) in entries(import.backward_downstream) {
+            mi.set_backward_downstream(k, v);
+        }
+        Ok(mi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct RequestId;
+
+    #[test]
+    fn test_to_json() {
+        let mut parent = MetaInfo::new();
+        parent.insert(42u32);
+        parent.insert_string("b".into(), "2".into());
+        let mut mi = MetaInfo::from(Arc::new(parent));
+        mi.insert_faststr::<RequestId>("r1".into());
+        mi.insert_string("a".into(), "1".into());
+        mi.set_persistent("TENANT", "t1");
+        mi.set_backward_downstream("COST", "10");
+        // removed types are not exported
+        mi.insert(1u8);
+        mi.remove::<u8>();
+
+        let expected = format!(
+            r#"{{
+  "strings": {{
+    "a": "1",
+    "b": "2"
+  }},
+  "persistent": {{
+    "TENANT": "t1"
+  }},
+  "transient": {{}},
+  "upstream": {{}},
+  "backward_transient": {{}},
+  "backward_downstream": {{
+    "COST": "10"
+  }},
+  "types": [
+    "{}",
+    "u32"
+  ]
+}}"#,
+            std::any::type_name::<RequestId>()
+        );
+        assert_eq!(mi.to_json(), expected);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut mi = MetaInfo::new();
+        mi.insert_string("a".into(), "1".into());
+        mi.set_persistent("TENANT", "t1");
+        mi.set_transient("USER", "u1");
+        mi.set_upstream("CALLER", "c1");
+        mi.set_backward_transient("COST", "10");
+        mi.set_backward_downstream("RETRY", "1");
+
+        let json = mi.to_json();
+        let imported = MetaInfo::from_json(&json).unwrap();
+        assert_eq!(imported.to_json(), json);
+    }
+
+    #[test]
+    fn test_from_json() {
+        let mi =
+            MetaInfo::from_json(r#"{"persistent": {"TENANT": "t1"}, "types": ["u32"]}"#).unwrap();
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+        assert!(!mi.contains::<u32>());
+
+        assert!(MetaInfo::from_json(r#"{"persistant": {}}"#).is_err());
+        assert!(MetaInfo::from_json(r#"{"persistent": {"TENANT": 1}}"#).is_err());
+    }
+}
@@ -94,30 +94,48 @@ impl Node {
     #[inline]
     pub fn extend(&mut self, other: Self) {
         if let Some(v) = other.persistent {
-            if self.persistent.is_none() {
-                self.persistent = Some(v);
-            } else {
-                self.persistent.as_mut().unwrap().extend(v);
+            match self.persistent.as_mut() {
+                Some(m) => m.extend(v),
+                None => self.persistent = Some(v),
             }
         }
 
         if let Some(v) = other.transient {
-            if self.transient.is_none() {
-                self.transient = Some(v);
-            } else {
-                self.transient.as_mut().unwrap().extend(v);
+            match self.transient.as_mut() {
+                Some(m) => m.extend(v),
+                None => self.transient = Some(v),
             }
         }
 
         if let Some(v) = other.stale {
-            if self.stale.is_none() {
-                self.stale = Some(v);
-            } else {
-                self.stale.as_mut().unwrap().extend(v);
+            match self.stale.as_mut() {
+                Some(m) => m.extend(v),
+                None => self.stale = Some(v),
             }
         }
     }
 
+    /// Get the approximate size of the heap allocations of the maps and their entries.
+    pub fn approx_heap_size(&self) -> usize {
+        [&self.persistent, &self.transient, &self.stale]
+            .into_iter()
+            .flatten()
+            .map(crate::heap::str_map_size)
+            .sum()
+    }
+
+    /// The persistent, transient and stale sections.
+    #[inline]
+    pub fn sections(&self) -> [&Option<AHashMap<FastStr, FastStr>>; 3] {
+        [&self.persistent, &self.transient, &self.stale]
+    }
+
+    /// The persistent, transient and stale sections.
+    #[inline]
+    pub fn sections_mut(&mut self) -> [&mut Option<AHashMap<FastStr, FastStr>>; 3] {
+        [&mut self.persistent, &mut self.transient, &mut self.stale]
+    }
+
     #[inline]
     pub fn clear(&mut self) {
         if let Some(v) = self.persistent.as_mut() {
@@ -1,22 +1,72 @@
+mod audit;
+mod context;
 mod convert;
+mod deadline;
+mod diff;
+mod dot;
+mod env;
 mod faststr_map;
+mod heap;
+mod hop;
 mod kv;
+mod merge;
+mod observe;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "tracing")]
+mod trace;
 mod type_map;
 
-use std::{fmt, sync::Arc};
+use std::{fmt, panic::Location, sync::Arc};
 
 use ahash::AHashMap;
+use audit::Audit;
+pub use audit::{AuditEntry, AuditJournal};
+#[cfg(feature = "task_local")]
+pub use context::MetaInfoContext;
+pub use context::{MetaInfoFields, MetaInfoSelector, WithMetaInfo};
 use convert::{Converter, HttpConverter, RpcConverter};
+pub use deadline::{DEADLINE_SKEW_MARGIN, DEADLINE_TIMEOUT_KEY};
+pub use diff::{DiffEntry, DiffKind, MetaInfoDiff};
+pub use env::EnvTransients;
 use faststr::FastStr;
 pub use faststr_map::FastStrMap;
+pub use heap::{HeapSize, HeapSizeReport};
+pub use hop::{HopError, HOP_COUNT_KEY, HOP_TRAIL_KEY};
 use kv::Node;
+pub use merge::{Conflict, ConflictKey, MergeReport, MergeStrategy, Section};
+use observe::Observers;
+pub use observe::{Mutation, MutationKind, Observer};
 use paste::paste;
+#[cfg(feature = "tracing")]
+pub use trace::{MetaInfoFormat, MetaInfoLayer};
 pub use type_map::TypeMap;
 
 pub mod backward;
+pub mod dubbo;
 pub mod forward;
+#[cfg(feature = "tonic")]
+pub mod grpc;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mq;
+pub mod process;
+pub mod propagation;
+pub mod scoped;
+pub mod web;
+pub mod wire;
 pub use backward::Backward;
 pub use forward::Forward;
+#[cfg(feature = "metrics")]
+use metrics::Direction;
+pub use propagation::{
+    Carrier, EscapedHttpPropagator, FallbackPropagator, HttpPropagator, Propagator, RpcPropagator,
+};
+pub use wire::DecodeError;
 
 #[cfg(feature = "task_local")]
 tokio::task_local! {
@@ -24,7 +74,6 @@ tokio::task_local! {
 }
 
 /// Framework should all obey these prefixes.
-
 pub const RPC_PREFIX_PERSISTENT: &str = "RPC_PERSIST_";
 pub const RPC_PREFIX_TRANSIENT: &str = "RPC_TRANSIT_";
 pub const RPC_PREFIX_BACKWARD: &str = "RPC_BACKWARD_";
@@ -74,6 +123,11 @@ pub struct MetaInfo {
     /// e.g. RPC
     forward_node: Option<kv::Node>,
     backward_node: Option<kv::Node>,
+
+    /// Observers of the mutations, inherited by children.
+    observers: Option<Observers>,
+    /// Audit journal, shared with children.
+    audit: Option<Audit>,
 }
 
 impl MetaInfo {
@@ -92,6 +146,8 @@ impl MetaInfo {
     pub fn from(parent: Arc<MetaInfo>) -> MetaInfo {
         let forward_node = parent.forward_node.clone();
         let backward_node = parent.backward_node.clone();
+        let observers = parent.observers.clone();
+        let audit = parent.audit.clone();
         MetaInfo {
             parent: Some(parent),
             tmap: None,
@@ -100,6 +156,8 @@ impl MetaInfo {
 
             forward_node,
             backward_node,
+            observers,
+            audit,
         }
     }
 
@@ -119,6 +177,8 @@ impl MetaInfo {
                 faststr_tmap: None,
                 forward_node: self.forward_node.clone(),
                 backward_node: self.backward_node.clone(),
+                observers: self.observers.clone(),
+                audit: self.audit.clone(),
             };
             (self, new)
         } else {
@@ -138,6 +198,8 @@ impl MetaInfo {
         forward_node: Option<kv::Node>,
         backward_node: Option<kv::Node>,
     ) -> MetaInfo {
+        let observers = parent.observers.clone();
+        let audit = parent.audit.clone();
         MetaInfo {
             parent: Some(parent),
             tmap: None,
@@ -146,12 +208,23 @@ impl MetaInfo {
 
             forward_node,
             backward_node,
+            observers,
+            audit,
         }
     }
 
     /// Insert a type into this `MetaInfo`.
     #[inline]
+    #[track_caller]
     pub fn insert<T: Send + Sync + 'static>(&mut self, val: T) {
+        if !self.observe_type::<T>(
+            MutationKind::Insert,
+            Section::Typed,
+            None,
+            Location::caller(),
+        ) {
+            return;
+        }
         self.tmap
             .get_or_insert_with(|| TypeMap::with_capacity(DEFAULT_MAP_SIZE))
             .insert(val);
@@ -159,7 +232,16 @@ impl MetaInfo {
 
     /// Insert a faststr newtype into this `MetaInfo`.
     #[inline]
+    #[track_caller]
     pub fn insert_faststr<T: Send + Sync + 'static>(&mut self, val: FastStr) {
+        if !self.observe_type::<T>(
+            MutationKind::Insert,
+            Section::FastStr,
+            Some(&val),
+            Location::caller(),
+        ) {
+            return;
+        }
         self.faststr_tmap
             .get_or_insert_with(|| FastStrMap::with_capacity(DEFAULT_MAP_SIZE))
             .insert::<T>(val);
@@ -167,7 +249,11 @@ impl MetaInfo {
 
     /// Insert a string k-v into this `MetaInfo`.
     #[inline]
+    #[track_caller]
     pub fn insert_string(&mut self, key: FastStr, val: FastStr) {
+        if !self.observe(MutationKind::Insert, Section::String, &key, Some(&val)) {
+            return;
+        }
         self.smap
             .get_or_insert_with(|| AHashMap::with_capacity(DEFAULT_MAP_SIZE))
             .insert(key, val);
@@ -227,37 +313,48 @@ impl MetaInfo {
     /// Get a reference to a type previously inserted on this `MetaInfo`.
     #[inline]
     pub fn get<T: 'static>(&self) -> Option<&T> {
-        self.tmap.as_ref().and_then(|tmap| tmap.get()).or_else(|| {
-            self.parent
-                .as_ref()
-                .and_then(|parent| parent.as_ref().get::<T>())
-        })
+        self.lookup(|mi| mi.tmap.as_ref().and_then(|tmap| tmap.get()))
     }
 
     /// Remove a type from this `MetaInfo` and return it.
     /// Can only remove the type in the current scope.
     #[inline]
+    #[track_caller]
     pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        if !self.observe_type::<T>(
+            MutationKind::Remove,
+            Section::Typed,
+            None,
+            Location::caller(),
+        ) {
+            return None;
+        }
         self.tmap.as_mut().and_then(|tmap| tmap.remove::<T>())
     }
 
     /// Get a reference to a faststr newtype previously inserted on this `MetaInfo`.
     #[inline]
     pub fn get_faststr<T: 'static>(&self) -> Option<&FastStr> {
-        self.faststr_tmap
-            .as_ref()
-            .and_then(|faststr_tmap: &FastStrMap| faststr_tmap.get::<T>())
-            .or_else(|| {
-                self.parent
-                    .as_ref()
-                    .and_then(|parent| parent.as_ref().get_faststr::<T>())
-            })
+        self.lookup(|mi| {
+            mi.faststr_tmap
+                .as_ref()
+                .and_then(|faststr_tmap: &FastStrMap| faststr_tmap.get::<T>())
+        })
     }
 
     /// Remove a faststr newtype from this `MetaInfo` and return it.
     /// Can only remove the type in the current scope.
     #[inline]
+    #[track_caller]
     pub fn remove_faststr<T: 'static>(&mut self) -> Option<FastStr> {
+        if !self.observe_type::<T>(
+            MutationKind::Remove,
+            Section::FastStr,
+            None,
+            Location::caller(),
+        ) {
+            return None;
+        }
         self.faststr_tmap
             .as_mut()
             .and_then(|faststr_tmap| faststr_tmap.remove::<T>())
@@ -266,20 +363,18 @@ impl MetaInfo {
     /// Get a reference to a string k-v previously inserted on this `MetaInfo`.
     #[inline]
     pub fn get_string<K: AsRef<str>>(&self, key: K) -> Option<&FastStr> {
-        self.smap
-            .as_ref()
-            .and_then(|smap| smap.get(key.as_ref()))
-            .or_else(|| {
-                self.parent
-                    .as_ref()
-                    .and_then(|parent| parent.as_ref().get_string(key))
-            })
+        let key = key.as_ref();
+        self.lookup(|mi| mi.smap.as_ref().and_then(|smap| smap.get(key)))
     }
 
     /// Remove a string k-v from this `MetaInfo` and return it.
     /// Can only remove the type in the current scope.
     #[inline]
+    #[track_caller]
     pub fn remove_string<K: AsRef<str>>(&mut self, key: K) -> Option<FastStr> {
+        if !self.observe(MutationKind::Remove, Section::String, key.as_ref(), None) {
+            return None;
+        }
         self.smap
             .as_mut()
             .and_then(|smap| smap.remove(key.as_ref()))
@@ -309,8 +404,17 @@ impl MetaInfo {
 
     /// Extends self with the items from another `MetaInfo`.
     /// Only extend the items in the current scope.
+    ///
+    /// The incoming entries are notified to the observers first, and the vetoed ones are skipped.
     #[inline]
-    pub fn extend(&mut self, other: MetaInfo) {
+    #[track_caller]
+    pub fn extend(&mut self, mut other: MetaInfo) {
+        self.observe_incoming(&mut other);
+        self.apply_incoming(other);
+    }
+
+    /// Extends self with all the items from another `MetaInfo`, without notifying the observers.
+    pub(crate) fn apply_incoming(&mut self, other: MetaInfo) {
         if let Some(tmap) = other.tmap {
             self.tmap
                 .get_or_insert_with(|| TypeMap::with_capacity(DEFAULT_MAP_SIZE))
@@ -330,18 +434,16 @@ impl MetaInfo {
         }
 
         if let Some(node) = other.forward_node {
-            if self.forward_node.is_none() {
-                self.forward_node = Some(node);
-            } else {
-                self.forward_node.as_mut().unwrap().extend(node);
+            match self.forward_node.as_mut() {
+                Some(n) => n.extend(node),
+                None => self.forward_node = Some(node),
             }
         }
 
         if let Some(node) = other.backward_node {
-            if self.backward_node.is_none() {
-                self.backward_node = Some(node);
-            } else {
-                self.backward_node.as_mut().unwrap().extend(node);
+            match self.backward_node.as_mut() {
+                Some(n) => n.extend(node),
+                None => self.backward_node = Some(node),
             }
         }
     }
@@ -374,14 +476,19 @@ macro_rules! get_impl {
 }
 
 macro_rules! set_impl {
-    ($name:ident,$node:ident,$func_name:ident) => {
+    ($name:ident,$node:ident,$func_name:ident,$section:ident) => {
         paste! {
             #[inline]
+            #[track_caller]
             fn [<set_ $name>]<K: Into<FastStr>, V: Into<FastStr>>(
                 &mut self,
                 key: K,
                 value: V,
             ) {
+                let (key, value) = (key.into(), value.into());
+                if !self.observe(MutationKind::Insert, Section::$section, &key, Some(&value)) {
+                    return;
+                }
                 self.[<ensure_ $node _node>]();
                 self.[<$node _node>]
                     .as_mut()
@@ -393,10 +500,14 @@ macro_rules! set_impl {
 }
 
 macro_rules! del_impl {
-    ($name:ident,$node:ident,$func_name:ident) => {
+    ($name:ident,$node:ident,$func_name:ident,$section:ident) => {
         paste! {
             #[inline]
+            #[track_caller]
             fn [<del_ $name>]<K: AsRef<str>>(&mut self, key: K) -> Option<FastStr> {
+                if !self.observe(MutationKind::Remove, Section::$section, key.as_ref(), None) {
+                    return None;
+                }
                 if let Some(node) = self.[<$node _node>].as_mut() {
                     node.[<del_ $func_name>](key)
                 } else {
@@ -412,13 +523,13 @@ impl forward::Forward for MetaInfo {
     get_impl!(transient, forward, transient);
     get_impl!(upstream, forward, stale);
 
-    set_impl!(persistent, forward, persistent);
-    set_impl!(transient, forward, transient);
-    set_impl!(upstream, forward, stale);
+    set_impl!(persistent, forward, persistent, Persistent);
+    set_impl!(transient, forward, transient, Transient);
+    set_impl!(upstream, forward, stale, Upstream);
 
-    del_impl!(persistent, forward, persistent);
-    del_impl!(transient, forward, transient);
-    del_impl!(upstream, forward, stale);
+    del_impl!(persistent, forward, persistent, Persistent);
+    del_impl!(transient, forward, transient, Transient);
+    del_impl!(upstream, forward, stale, Upstream);
 
     #[inline]
     fn get_all_persistents(&self) -> Option<&AHashMap<FastStr, FastStr>> {
@@ -448,14 +559,14 @@ impl forward::Forward for MetaInfo {
     fn get_all_persistents_and_transients_with_rpc_prefix(
         &self,
     ) -> Option<AHashMap<FastStr, FastStr>> {
-        self.get_all_persistents_and_transients_with_prefix(RpcConverter)
+        self.get_all_persistents_and_transients_with_prefix(RpcPropagator)
     }
 
     #[inline]
     fn get_all_persistents_and_transients_with_http_prefix(
         &self,
     ) -> Option<AHashMap<FastStr, FastStr>> {
-        self.get_all_persistents_and_transients_with_prefix(HttpConverter)
+        self.get_all_persistents_and_transients_with_prefix(HttpPropagator)
     }
 
     #[inline]
@@ -473,51 +584,58 @@ impl forward::Forward for MetaInfo {
     }
 
     #[inline]
+    #[track_caller]
     fn strip_rpc_prefix_and_set_persistent<K: AsRef<str>, V: Into<FastStr>>(
         &mut self,
         key: K,
         value: V,
     ) {
-        let key = key.as_ref();
-        if let Some(key) = RpcConverter.remove_persistent_prefix(key) {
-            self.set_persistent(key, value);
-        }
+        self.strip_prefix_and_set(
+            RpcPropagator,
+            Section::Persistent,
+            key.as_ref(),
+            value.into(),
+        );
     }
 
     #[inline]
+    #[track_caller]
     fn strip_rpc_prefix_and_set_upstream<K: AsRef<str>, V: Into<FastStr>>(
         &mut self,
         key: K,
         value: V,
     ) {
-        let key = key.as_ref();
-        if let Some(key) = RpcConverter.remove_transient_prefix(key) {
-            self.set_upstream(key, value);
-        }
+        self.strip_prefix_and_set(RpcPropagator, Section::Upstream, key.as_ref(), value.into());
     }
 
     #[inline]
+    #[track_caller]
     fn strip_http_prefix_and_set_persistent<K: AsRef<str>, V: Into<FastStr>>(
         &mut self,
         key: K,
         value: V,
     ) {
-        let key = key.as_ref();
-        if let Some(key) = HttpConverter.remove_persistent_prefix(key) {
-            self.set_persistent(key, value);
-        }
+        self.strip_prefix_and_set(
+            HttpPropagator,
+            Section::Persistent,
+            key.as_ref(),
+            value.into(),
+        );
     }
 
     #[inline]
+    #[track_caller]
     fn strip_http_prefix_and_set_upstream<K: AsRef<str>, V: Into<FastStr>>(
         &mut self,
         key: K,
         value: V,
     ) {
-        let key = key.as_ref();
-        if let Some(key) = HttpConverter.remove_transient_prefix(key) {
-            self.set_upstream(key, value);
-        }
+        self.strip_prefix_and_set(
+            HttpPropagator,
+            Section::Upstream,
+            key.as_ref(),
+            value.into(),
+        );
     }
 }
 
@@ -525,11 +643,11 @@ impl backward::Backward for MetaInfo {
     get_impl!(backward_transient, backward, transient);
     get_impl!(backward_downstream, backward, stale);
 
-    set_impl!(backward_transient, backward, transient);
-    set_impl!(backward_downstream, backward, stale);
+    set_impl!(backward_transient, backward, transient, BackwardTransient);
+    set_impl!(backward_downstream, backward, stale, BackwardDownstream);
 
-    del_impl!(backward_transient, backward, transient);
-    del_impl!(backward_downstream, backward, stale);
+    del_impl!(backward_transient, backward, transient, BackwardTransient);
+    del_impl!(backward_downstream, backward, stale, BackwardDownstream);
 
     fn get_all_backward_transients(&self) -> Option<&AHashMap<FastStr, FastStr>> {
         match self.backward_node.as_ref() {
@@ -565,66 +683,113 @@ impl backward::Backward for MetaInfo {
         self.iter_all_backword_transients_with_prefix(HttpConverter)
     }
 
+    #[track_caller]
     fn strip_rpc_prefix_and_set_backward_downstream<K: AsRef<str>, V: Into<FastStr>>(
         &mut self,
         key: K,
         value: V,
     ) {
-        let key = key.as_ref();
-        if let Some(key) = RpcConverter.remove_backward_prefix(key) {
-            self.set_backward_downstream(key, value);
-        }
+        self.strip_prefix_and_set(
+            RpcPropagator,
+            Section::BackwardDownstream,
+            key.as_ref(),
+            value.into(),
+        );
     }
 
+    #[track_caller]
     fn strip_http_prefix_and_set_backward_downstream<K: AsRef<str>, V: Into<FastStr>>(
         &mut self,
         key: K,
         value: V,
     ) {
-        let key = key.as_ref();
-        if let Some(key) = HttpConverter.remove_backward_prefix(key) {
-            self.set_backward_downstream(key, value);
-        }
+        self.strip_prefix_and_set(
+            HttpPropagator,
+            Section::BackwardDownstream,
+            key.as_ref(),
+            value.into(),
+        );
     }
 }
 
 impl MetaInfo {
+    /// Find the first entry in the scope chain, starting from the current scope.
     #[inline]
-    fn get_all_persistents_and_transients_with_prefix<C>(
+    fn lookup<'a, V: ?Sized>(&'a self, f: impl Fn(&'a MetaInfo) -> Option<&'a V>) -> Option<&'a V> {
+        let mut cur = self;
+        #[cfg(feature = "metrics")]
+        let mut depth = 0;
+        loop {
+            if let Some(v) = f(cur) {
+                #[cfg(feature = "metrics")]
+                metrics::recorder().record_lookup_depth(depth);
+                return Some(v);
+            }
+            match cur.parent.as_deref() {
+                Some(parent) => {
+                    cur = parent;
+                    #[cfg(feature = "metrics")]
+                    {
+                        depth += 1;
+                    }
+                }
+                None => {
+                    #[cfg(feature = "metrics")]
+                    metrics::recorder().record_lookup_depth(depth);
+                    return None;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn get_all_persistents_and_transients_with_prefix<P>(
         &self,
-        converter: C,
+        propagator: P,
     ) -> Option<AHashMap<FastStr, FastStr>>
     where
-        C: Converter,
+        P: Propagator,
     {
-        match self.forward_node.as_ref() {
-            Some(node) => {
-                let persistents = node.get_all_persistents();
-                let transients = node.get_all_transients();
-                let new_cap = persistents.map(|p| p.len()).unwrap_or(0)
-                    + transients.map(|t| t.len()).unwrap_or(0);
-                if new_cap == 0 {
-                    return None;
-                }
-                let mut map = AHashMap::with_capacity(new_cap);
-                if let Some(persistents) = persistents {
-                    map.extend(
-                        persistents
-                            .iter()
-                            .map(|(k, v)| (converter.add_persistent_prefix(k), v.clone())),
-                    );
-                }
-                if let Some(transients) = transients {
-                    map.extend(
-                        transients
-                            .iter()
-                            .map(|(k, v)| (converter.add_transient_prefix(k), v.clone())),
-                    );
-                }
-                Some(map)
-            }
-            None => None,
+        let persistents = self
+            .forward_node
+            .as_ref()
+            .and_then(|n| n.get_all_persistents());
+        let transients = self
+            .forward_node
+            .as_ref()
+            .and_then(|n| n.get_all_transients());
+        let new_cap = persistents.map(|p| p.len()).unwrap_or(0)
+            + transients.map(|t| t.len()).unwrap_or(0)
+            + self.deadline().is_some() as usize;
+        if new_cap == 0 {
+            return None;
         }
+        let mut map = AHashMap::with_capacity(new_cap);
+        propagator.inject(self, &mut map);
+        Some(map)
+    }
+
+    /// Extract an entry into the given section with the propagator, recording the import.
+    #[inline]
+    #[track_caller]
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn strip_prefix_and_set<P: Propagator>(
+        &mut self,
+        propagator: P,
+        section: Section,
+        key: &str,
+        value: FastStr,
+    ) {
+        #[cfg(feature = "metrics")]
+        let v = value.clone();
+        let imported = match section {
+            Section::Persistent => propagator.extract_persistent(self, key, value),
+            Section::Upstream => propagator.extract_upstream(self, key, value),
+            Section::BackwardDownstream => propagator.extract_backward_downstream(self, key, value),
+            _ => unreachable!("{section} entries are not imported"),
+        };
+        #[cfg(feature = "metrics")]
+        metrics::record_import(section, key, &v, imported);
     }
 
     #[inline]
@@ -635,20 +800,56 @@ impl MetaInfo {
     where
         C: Converter + Copy + 'static,
     {
-        self.forward_node
-            .as_ref()
+        let persistents = self
+            .get_all_persistents()
             .into_iter()
-            .flat_map(move |node| {
-                let persistents = node.get_all_persistents().into_iter().flat_map(move |p| {
-                    p.into_iter()
-                        .map(move |(k, v)| (converter.add_persistent_prefix(k), v))
-                });
-                let transients = node.get_all_transients().into_iter().flat_map(move |t| {
-                    t.into_iter()
-                        .map(move |(k, v)| (converter.add_transient_prefix(k), v))
-                });
-                persistents.chain(transients)
-            })
+            .flatten()
+            .map(move |(k, v)| {
+                let k = converter.add_persistent_prefix(k);
+                #[cfg(feature = "metrics")]
+                metrics::record_entry(Direction::Export, Section::Persistent, &k, v);
+                (k, v)
+            });
+        let transients = self
+            .get_all_transients()
+            .into_iter()
+            .flatten()
+            .map(move |(k, v)| {
+                let k = converter.add_transient_prefix(k);
+                #[cfg(feature = "metrics")]
+                metrics::record_entry(Direction::Export, Section::Transient, &k, v);
+                (k, v)
+            });
+        persistents.chain(transients)
+    }
+
+    /// Iterate over the forward persistents and transients with prefix, followed by the remaining
+    /// timeout of the deadline.
+    #[inline]
+    pub(crate) fn iter_forward_with_deadline<C>(
+        &self,
+        converter: C,
+    ) -> impl Iterator<Item = (FastStr, FastStr)> + '_
+    where
+        C: Converter + Copy + 'static,
+    {
+        self.iter_all_persistents_and_transients_with_prefix(converter)
+            .map(|(k, v)| (k, v.clone()))
+            .chain(self.deadline_header_with_prefix(converter))
+    }
+
+    /// Iterate over the forward transients without prefix, including the remaining timeout of the
+    /// deadline.
+    #[inline]
+    fn iter_transients_with_deadline(&self) -> impl Iterator<Item = (&str, FastStr)> {
+        self.get_all_transients()
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| (k.as_str(), v.clone()))
+            .chain(
+                self.deadline_timeout_ms()
+                    .map(|timeout| (DEADLINE_TIMEOUT_KEY, timeout)),
+            )
     }
 
     #[inline]
@@ -667,10 +868,12 @@ impl MetaInfo {
                         return None;
                     }
                     let mut map = AHashMap::with_capacity(new_cap);
-                    map.extend(
-                        t.iter()
-                            .map(|(k, v)| (converter.add_transient_prefix(k), v.clone())),
-                    );
+                    map.extend(t.iter().map(|(k, v)| {
+                        let k = converter.add_transient_prefix(k);
+                        #[cfg(feature = "metrics")]
+                        metrics::record_entry(Direction::Export, Section::BackwardTransient, &k, v);
+                        (k, v.clone())
+                    }));
                     Some(map)
                 } else {
                     None
@@ -696,7 +899,12 @@ impl MetaInfo {
                     .into_iter()
                     .flat_map(|t| t.into_iter())
             })
-            .map(move |(k, v)| (converter.add_transient_prefix(k), v))
+            .map(move |(k, v)| {
+                let k = converter.add_transient_prefix(k);
+                #[cfg(feature = "metrics")]
+                metrics::record_entry(Direction::Export, Section::BackwardTransient, &k, v);
+                (k, v)
+            })
     }
 }
 
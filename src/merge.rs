@@ -0,0 +1,458 @@
+//! Merging of [`MetaInfo`]s with conflict resolution.
+
+use std::{any::TypeId, fmt, panic::Location};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+
+use crate::{kv::Node, MetaInfo, MutationKind};
+
+/// A section of [`MetaInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Section {
+    /// Typed entries inserted with [`MetaInfo::insert`].
+    Typed,
+    /// FastStr newtype entries inserted with [`MetaInfo::insert_faststr`].
+    FastStr,
+    /// String k-v entries inserted with [`MetaInfo::insert_string`].
+    String,
+    /// Forward persistents.
+    Persistent,
+    /// Forward transients.
+    Transient,
+    /// Forward upstreams.
+    Upstream,
+    /// Backward transients.
+    BackwardTransient,
+    /// Backward downstreams.
+    BackwardDownstream,
+}
+
+impl Section {
+    /// All the sections, in order.
+    pub const ALL: [Section; 8] = [
+        Section::Typed,
+        Section::FastStr,
+        Section::String,
+        Section::Persistent,
+        Section::Transient,
+        Section::Upstream,
+        Section::BackwardTransient,
+        Section::BackwardDownstream,
+    ];
+
+    // the sections of the persistent, transient and stale maps of the nodes, backward nodes
+    // have no persistent
+    pub(crate) const FORWARD: [Option<Section>; 3] = [
+        Some(Section::Persistent),
+        Some(Section::Transient),
+        Some(Section::Upstream),
+    ];
+    pub(crate) const BACKWARD: [Option<Section>; 3] = [
+        None,
+        Some(Section::BackwardTransient),
+        Some(Section::BackwardDownstream),
+    ];
+
+    /// The name of the section, e.g. `backward_transient`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Section::Typed => "typed",
+            Section::FastStr => "faststr",
+            Section::String => "strings",
+            Section::Persistent => "persistent",
+            Section::Transient => "transient",
+            Section::Upstream => "upstream",
+            Section::BackwardTransient => "backward_transient",
+            Section::BackwardDownstream => "backward_downstream",
+        }
+    }
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How [`MetaInfo::extend_with`] resolves entries present on both sides with different values.
+pub enum MergeStrategy<'a> {
+    /// Keep the existing entries.
+    KeepExisting,
+    /// Overwrite with the incoming entries, which is what [`MetaInfo::extend`] does.
+    Overwrite,
+    /// Call the resolver with the section, key, existing and incoming values of each conflicting
+    /// string entry, and set the returned value.
+    ///
+    /// Faststr entries are resolved like the string ones, with their type name as the key. Typed
+    /// entries can't be compared nor resolved by value, the existing ones are kept.
+    Resolve(&'a dyn Fn(Section, &str, &FastStr, &FastStr) -> FastStr),
+}
+
+impl fmt::Debug for MergeStrategy<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeStrategy::KeepExisting => f.write_str("KeepExisting"),
+            MergeStrategy::Overwrite => f.write_str("Overwrite"),
+            MergeStrategy::Resolve(_) => f.write_str("Resolve"),
+        }
+    }
+}
+
+/// The key of a conflicting entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictKey {
+    /// The type of a typed or faststr entry.
+    Type {
+        /// The id of the type.
+        id: TypeId,
+        /// The name of the type, as given by [`std::any::type_name`] with the `type_names`
+        /// feature, or the `Debug` output of the id otherwise.
+        name: FastStr,
+    },
+    /// The key of a string entry.
+    Str(FastStr),
+}
+
+/// An entry present on both sides of a merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The section of the entry.
+    pub section: Section,
+    /// The key of the entry.
+    pub key: ConflictKey,
+}
+
+/// The report of [`MetaInfo::extend_with`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MergeReport {
+    /// The conflicting entries, in no particular order.
+    ///
+    /// Typed entries conflict as soon as both sides have the type, other entries conflict if
+    /// their values differ.
+    pub conflicts: Vec<Conflict>,
+}
+
+impl MergeReport {
+    /// Check if the merge had no conflict.
+    #[inline]
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// Get the conflicting keys of a string section.
+    pub fn keys(&self, section: Section) -> impl Iterator<Item = &FastStr> {
+        self.conflicts
+            .iter()
+            .filter(move |c| c.section == section)
+            .filter_map(|c| match &c.key {
+                ConflictKey::Str(key) => Some(key),
+                ConflictKey::Type { .. } => None,
+            })
+    }
+
+    /// Get the conflicting type names of a typed or faststr section.
+    pub fn type_names(&self, section: Section) -> impl Iterator<Item = &FastStr> {
+        self.conflicts
+            .iter()
+            .filter(move |c| c.section == section)
+            .filter_map(|c| match &c.key {
+                ConflictKey::Type { name, .. } => Some(name),
+                ConflictKey::Str(_) => None,
+            })
+    }
+}
+
+// Resolve the incoming value of an entry against the existing one in place, returns whether the
+// incoming value is to be applied.
+fn resolve_value(
+    strategy: &MergeStrategy<'_>,
+    section: Section,
+    key: &str,
+    existing: Option<&FastStr>,
+    incoming: &mut FastStr,
+    conflict: impl FnOnce() -> ConflictKey,
+    report: &mut MergeReport,
+) -> bool {
+    let Some(existing) = existing else {
+        return true;
+    };
+    if existing == incoming {
+        return false;
+    }
+    report.conflicts.push(Conflict {
+        section,
+        key: conflict(),
+    });
+    match strategy {
+        MergeStrategy::KeepExisting => false,
+        MergeStrategy::Overwrite => true,
+        MergeStrategy::Resolve(resolve) => {
+            *incoming = resolve(section, key, existing, incoming);
+            true
+        }
+    }
+}
+
+impl MetaInfo {
+    /// Extends self with the items from another `MetaInfo`, resolving the entries present on both
+    /// sides with the given strategy.
+    /// Only extend the items in the current scope.
+    ///
+    /// The entries to be applied, with their resolved values, are notified to the observers
+    /// first, and the vetoed ones are skipped.
+    ///
+    /// Returns the report of the conflicting entries.
+    #[track_caller]
+    pub fn extend_with(&mut self, mut other: MetaInfo, strategy: MergeStrategy<'_>) -> MergeReport {
+        let report = self.resolve_incoming(&mut other, &strategy, Location::caller());
+        self.apply_incoming(other);
+        report
+    }
+
+    // Keep only the entries of `other` to be applied and allowed by the observers, with their
+    // resolved values.
+    fn resolve_incoming(
+        &self,
+        other: &mut MetaInfo,
+        strategy: &MergeStrategy<'_>,
+        location: &'static Location<'static>,
+    ) -> MergeReport {
+        let mut report = MergeReport::default();
+        let insert = MutationKind::Insert;
+
+        if let Some(tmap) = other.tmap.as_mut() {
+            tmap.retain(|id, name| {
+                if self.tmap.as_ref().is_some_and(|tmap| tmap.contains_id(id)) {
+                    report.conflicts.push(Conflict {
+                        section: Section::Typed,
+                        key: ConflictKey::Type {
+                            id: *id,
+                            name: name.clone(),
+                        },
+                    });
+                    if !matches!(strategy, MergeStrategy::Overwrite) {
+                        return false;
+                    }
+                }
+                self.observe_at(insert, Section::Typed, name, None, location)
+            });
+        }
+
+        if let Some(faststr_tmap) = other.faststr_tmap.as_mut() {
+            faststr_tmap.retain(|id, name, v| {
+                let existing = self
+                    .faststr_tmap
+                    .as_ref()
+                    .and_then(|faststr_tmap| faststr_tmap.get_by_id(id));
+                let conflict = || ConflictKey::Type {
+                    id: *id,
+                    name: name.clone(),
+                };
+                resolve_value(
+                    strategy,
+                    Section::FastStr,
+                    name,
+                    existing,
+                    v,
+                    conflict,
+                    &mut report,
+                ) && self.observe_at(insert, Section::FastStr, name, Some(v), location)
+            });
+        }
+
+        let mut resolve_map =
+            |section: Section,
+             this: Option<&AHashMap<FastStr, FastStr>>,
+             other: Option<&mut AHashMap<FastStr, FastStr>>| {
+                let Some(other) = other else {
+                    return;
+                };
+                other.retain(|k, v| {
+                    let existing = this.and_then(|this| this.get(k));
+                    let conflict = || ConflictKey::Str(k.clone());
+                    resolve_value(strategy, section, k, existing, v, conflict, &mut report)
+                        && self.observe_at(insert, section, k, Some(v), location)
+                });
+            };
+
+        resolve_map(Section::String, self.smap.as_ref(), other.smap.as_mut());
+        for (sections, this, other) in [
+            (
+                Section::FORWARD,
+                &self.forward_node,
+                &mut other.forward_node,
+            ),
+            (
+                Section::BACKWARD,
+                &self.backward_node,
+                &mut other.backward_node,
+            ),
+        ] {
+            let Some(other) = other.as_mut() else {
+                continue;
+            };
+            let this = this.as_ref().map(Node::sections);
+            for (i, (section, other)) in sections.into_iter().zip(other.sections_mut()).enumerate()
+            {
+                if let Some(section) = section {
+                    let this = this.and_then(|this| this[i].as_ref());
+                    resolve_map(section, this, other.as_mut());
+                }
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{type_map::TypeNames, Backward, Forward, Mutation};
+
+    fn local() -> MetaInfo {
+        let mut mi = MetaInfo::new();
+        mi.insert(1u32);
+        mi.insert_string("region".into(), "local".into());
+        mi.set_persistent("TENANT", "default");
+        mi.set_persistent("LOCALE", "en");
+        mi.set_backward_downstream("COST", "1");
+        mi
+    }
+
+    fn incoming() -> MetaInfo {
+        let mut mi = MetaInfo::new();
+        mi.insert(2u32);
+        mi.insert_string("region".into(), "remote".into());
+        mi.set_persistent("TENANT", "t1");
+        mi.set_persistent("LOCALE", "en");
+        mi.set_upstream("USER", "u1");
+        mi.set_backward_downstream("COST", "2");
+        mi
+    }
+
+    #[test]
+    fn test_keep_existing() {
+        let mut mi = local();
+        let report = mi.extend_with(incoming(), MergeStrategy::KeepExisting);
+        assert_eq!(*mi.get::<u32>().unwrap(), 1);
+        assert_eq!(mi.get_string("region").unwrap(), "local");
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "default");
+        assert_eq!(mi.get_upstream("USER").unwrap(), "u1");
+        assert_eq!(mi.get_backward_downstream("COST").unwrap(), "1");
+
+        assert_eq!(report.conflicts.len(), 4);
+        assert!(report.conflicts.contains(&Conflict {
+            section: Section::Typed,
+            key: ConflictKey::Type {
+                id: TypeId::of::<u32>(),
+                name: TypeNames::name_of::<u32>(),
+            },
+        }));
+        assert_eq!(
+            report.type_names(Section::Typed).collect::<Vec<_>>(),
+            [&TypeNames::name_of::<u32>()]
+        );
+        assert_eq!(
+            report.keys(Section::Persistent).collect::<Vec<_>>(),
+            ["TENANT"]
+        );
+        assert_eq!(
+            report.keys(Section::BackwardDownstream).collect::<Vec<_>>(),
+            ["COST"]
+        );
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let mut mi = local();
+        let report = mi.extend_with(incoming(), MergeStrategy::Overwrite);
+        assert_eq!(*mi.get::<u32>().unwrap(), 2);
+        assert_eq!(mi.get_string("region").unwrap(), "remote");
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(mi.get_backward_downstream("COST").unwrap(), "2");
+        assert!(!report.is_clean());
+
+        let mut mi = MetaInfo::new();
+        assert!(mi.extend_with(local(), MergeStrategy::Overwrite).is_clean());
+        assert_eq!(mi.get_persistent("LOCALE").unwrap(), "en");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let resolve = |section: Section, key: &str, existing: &FastStr, incoming: &FastStr| match (
+            section, key,
+        ) {
+            (Section::Persistent, "TENANT") => incoming.clone(),
+            _ => FastStr::from_string(format!("{existing}+{incoming}")),
+        };
+        let mut mi = local();
+        let report = mi.extend_with(incoming(), MergeStrategy::Resolve(&resolve));
+        assert_eq!(report.conflicts.len(), 4);
+        assert_eq!(*mi.get::<u32>().unwrap(), 1);
+        assert_eq!(mi.get_string("region").unwrap(), "local+remote");
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(mi.get_backward_downstream("COST").unwrap(), "1+2");
+    }
+
+    #[test]
+    fn test_observe_applied() {
+        let observe = |strategy: MergeStrategy<'_>| {
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let mut mi = local();
+            mi.add_observer({
+                let log = log.clone();
+                move |_: &MetaInfo, m: &Mutation<'_>| {
+                    log.lock()
+                        .unwrap()
+                        .push(format!("{} {} {:?}", m.section, m.key, m.value));
+                    m.key != "USER"
+                }
+            });
+            mi.extend_with(incoming(), strategy);
+            assert!(mi.get_upstream("USER").is_none());
+            let mut log = log.lock().unwrap().clone();
+            log.sort();
+            log
+        };
+
+        // the conflicting entries are kept, and the equal ones untouched
+        assert_eq!(
+            observe(MergeStrategy::KeepExisting),
+            ["upstream USER Some(\"u1\")"]
+        );
+
+        let resolve = |_: Section, _: &str, existing: &FastStr, incoming: &FastStr| {
+            FastStr::from_string(format!("{existing}+{incoming}"))
+        };
+        assert_eq!(
+            observe(MergeStrategy::Resolve(&resolve)),
+            [
+                "backward_downstream COST Some(\"1+2\")",
+                "persistent TENANT Some(\"default+t1\")",
+                "strings region Some(\"local+remote\")",
+                "upstream USER Some(\"u1\")",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_faststr() {
+        struct Rid;
+
+        let mut mi = MetaInfo::new();
+        mi.insert_faststr::<Rid>("a".into());
+        let mut other = MetaInfo::new();
+        other.insert_faststr::<Rid>("b".into());
+        let resolve = |section: Section, _: &str, existing: &FastStr, incoming: &FastStr| {
+            assert_eq!(section, Section::FastStr);
+            FastStr::from_string(format!("{existing}{incoming}"))
+        };
+        let report = mi.extend_with(other, MergeStrategy::Resolve(&resolve));
+        assert_eq!(report.type_names(Section::FastStr).count(), 1);
+        assert_eq!(mi.get_faststr::<Rid>().unwrap(), "ab");
+    }
+}
@@ -0,0 +1,594 @@
+//! Metrics of the propagation of [`MetaInfo`](crate::MetaInfo).
+//!
+//! With the `metrics` feature, the following events are reported to the global [`Recorder`]:
+//! - entries exported by `iter_*_with_{rpc,http}_prefix`, `get_all_*_with_{rpc,http}_prefix`
+//!   and the [`Propagator`](crate::Propagator)s;
+//! - entries imported by `strip_{rpc,http}_prefix_and_set_*` and the
+//!   [`Propagator`](crate::Propagator)s, and the keys filtered out because they don't have the
+//!   expected prefix;
+//! - the depth of the scope chain walked by `get`, `get_faststr` and `get_string`, that is, `0`
+//!   if the entry is found in the current scope.
+//!
+//! Lookups are frequent, so the [`InMemoryRecorder`] counts them on per-thread shards to avoid
+//! contention.
+//!
+//! The default recorder is the in-memory [`default_recorder`], another one can be installed once
+//! with [`set_recorder`], e.g. the [`FacadeRecorder`] reporting to the exporter installed for the
+//! [`metrics`](::metrics) crate:
+//! ```rust
+//! use metainfo::metrics::{set_recorder, FacadeRecorder};
+//!
+//! assert!(set_recorder(&FacadeRecorder).is_ok());
+//! ```
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+use crate::Section;
+
+const SECTIONS: usize = Section::ALL.len();
+
+const LOOKUP_SHARDS: usize = 16;
+
+/// The direction of a propagated entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Export,
+    Import,
+}
+
+impl Direction {
+    /// The name of the direction, e.g. `export`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Export => "export",
+            Direction::Import => "import",
+        }
+    }
+}
+
+/// A recorder of propagation metrics.
+pub trait Recorder: Send + Sync + 'static {
+    /// An entry of `bytes` bytes, key with prefix and value, is exported or imported.
+    fn record_entry(&self, direction: Direction, section: Section, bytes: usize);
+
+    /// A key is filtered out.
+    fn record_filtered(&self, direction: Direction, section: Section);
+
+    /// A lookup walked `depth` parents.
+    fn record_lookup_depth(&self, depth: usize);
+}
+
+#[derive(Debug)]
+struct Counters {
+    keys: AtomicU64,
+    bytes: AtomicU64,
+    filtered: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            keys: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            filtered: AtomicU64::new(0),
+        }
+    }
+
+    fn stats(&self) -> SectionStats {
+        SectionStats {
+            keys: self.keys.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// aligned to a cache line, so the shards of different threads don't contend
+#[derive(Debug)]
+#[repr(align(64))]
+struct LookupShard {
+    lookups: AtomicU64,
+    depth_sum: AtomicU64,
+    depth_max: AtomicU64,
+}
+
+impl LookupShard {
+    const fn new() -> Self {
+        LookupShard {
+            lookups: AtomicU64::new(0),
+            depth_sum: AtomicU64::new(0),
+            depth_max: AtomicU64::new(0),
+        }
+    }
+}
+
+/// The lookup shard of the current thread, threads are assigned to the shards in turn.
+#[inline]
+fn lookup_shard() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static SHARD: usize = NEXT.fetch_add(1, Ordering::Relaxed) % LOOKUP_SHARDS;
+    }
+    SHARD.with(|shard| *shard)
+}
+
+/// A [`Recorder`] keeping the metrics in memory.
+#[derive(Debug)]
+pub struct InMemoryRecorder {
+    export: [Counters; SECTIONS],
+    import: [Counters; SECTIONS],
+    lookups: [LookupShard; LOOKUP_SHARDS],
+}
+
+impl InMemoryRecorder {
+    /// Creates an empty `InMemoryRecorder`.
+    pub const fn new() -> Self {
+        InMemoryRecorder {
+            export: [const { Counters::new() }; SECTIONS],
+            import: [const { Counters::new() }; SECTIONS],
+            lookups: [const { LookupShard::new() }; LOOKUP_SHARDS],
+        }
+    }
+
+    #[inline]
+    fn counters(&self, direction: Direction, section: Section) -> &Counters {
+        match direction {
+            Direction::Export => &self.export[section as usize],
+            Direction::Import => &self.import[section as usize],
+        }
+    }
+
+    /// Get the current metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            export: self.export.each_ref().map(Counters::stats),
+            import: self.import.each_ref().map(Counters::stats),
+            lookups: self
+                .lookups
+                .iter()
+                .map(|s| s.lookups.load(Ordering::Relaxed))
+                .sum(),
+            lookup_depth_sum: self
+                .lookups
+                .iter()
+                .map(|s| s.depth_sum.load(Ordering::Relaxed))
+                .sum(),
+            lookup_depth_max: self
+                .lookups
+                .iter()
+                .map(|s| s.depth_max.load(Ordering::Relaxed))
+                .max()
+                .unwrap_or(0),
+        }
+    }
+}
+
+impl Default for InMemoryRecorder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder for InMemoryRecorder {
+    #[inline]
+    fn record_entry(&self, direction: Direction, section: Section, bytes: usize) {
+        let counters = self.counters(direction, section);
+        counters.keys.fetch_add(1, Ordering::Relaxed);
+        counters.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn record_filtered(&self, direction: Direction, section: Section) {
+        self.counters(direction, section)
+            .filtered
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn record_lookup_depth(&self, depth: usize) {
+        let shard = &self.lookups[lookup_shard()];
+        shard.lookups.fetch_add(1, Ordering::Relaxed);
+        if depth > 0 {
+            shard.depth_sum.fetch_add(depth as u64, Ordering::Relaxed);
+            shard.depth_max.fetch_max(depth as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A [`Recorder`] forwarding to the global recorder of the [`metrics`](::metrics) crate.
+///
+/// The entries are reported as the `metainfo_entries_total`, `metainfo_entry_bytes_total` and
+/// `metainfo_filtered_total` counters, labeled with the `direction` and the `section`, and the
+/// lookup depths as the `metainfo_lookup_depth` histogram.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FacadeRecorder;
+
+impl Recorder for FacadeRecorder {
+    #[inline]
+    fn record_entry(&self, direction: Direction, section: Section, bytes: usize) {
+        let labels = [
+            ("direction", direction.as_str()),
+            ("section", section.as_str()),
+        ];
+        ::metrics::counter!("metainfo_entries_total", &labels).increment(1);
+        ::metrics::counter!("metainfo_entry_bytes_total", &labels).increment(bytes as u64);
+    }
+
+    #[inline]
+    fn record_filtered(&self, direction: Direction, section: Section) {
+        let labels = [
+            ("direction", direction.as_str()),
+            ("section", section.as_str()),
+        ];
+        ::metrics::counter!("metainfo_filtered_total", &labels).increment(1);
+    }
+
+    #[inline]
+    fn record_lookup_depth(&self, depth: usize) {
+        ::metrics::histogram!("metainfo_lookup_depth").record(depth as f64);
+    }
+}
+
+/// The metrics of a section in one direction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SectionStats {
+    /// The number of entries.
+    pub keys: u64,
+    /// The total size of the entries, keys with prefix and values.
+    pub bytes: u64,
+    /// The number of keys filtered out.
+    pub filtered: u64,
+}
+
+/// The metrics of an [`InMemoryRecorder`] at some point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    export: [SectionStats; SECTIONS],
+    import: [SectionStats; SECTIONS],
+    /// The number of lookups.
+    pub lookups: u64,
+    /// The sum of the scope chain depths walked by the lookups.
+    pub lookup_depth_sum: u64,
+    /// The maximum scope chain depth walked by a lookup.
+    pub lookup_depth_max: u64,
+}
+
+impl MetricsSnapshot {
+    /// Get the metrics of a section in the given direction.
+    #[inline]
+    pub fn get(&self, direction: Direction, section: Section) -> SectionStats {
+        match direction {
+            Direction::Export => self.export[section as usize],
+            Direction::Import => self.import[section as usize],
+        }
+    }
+}
+
+/// The non-empty sections, one per line, e.g. `export persistent: keys=1 bytes=14 filtered=0`.
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (direction, stats) in [("export", &self.export), ("import", &self.import)] {
+            for (section, s) in Section::ALL.iter().zip(stats) {
+                if *s != SectionStats::default() {
+                    writeln!(
+                        f,
+                        "{direction} {section}: keys={} bytes={} filtered={}",
+                        s.keys, s.bytes, s.filtered
+                    )?;
+                }
+            }
+        }
+        write!(
+            f,
+            "lookups={} depth_sum={} depth_max={}",
+            self.lookups, self.lookup_depth_sum, self.lookup_depth_max
+        )
+    }
+}
+
+static DEFAULT_RECORDER: InMemoryRecorder = InMemoryRecorder::new();
+static RECORDER: OnceLock<&'static dyn Recorder> = OnceLock::new();
+
+/// Get the in-memory recorder used when no other recorder is installed.
+#[inline]
+pub fn default_recorder() -> &'static InMemoryRecorder {
+    &DEFAULT_RECORDER
+}
+
+/// Install the global recorder, returns it back if a recorder is already installed.
+pub fn set_recorder(recorder: &'static dyn Recorder) -> Result<(), &'static dyn Recorder> {
+    RECORDER.set(recorder)
+}
+
+/// Get the global recorder.
+#[inline]
+pub fn recorder() -> &'static dyn Recorder {
+    RECORDER.get().copied().unwrap_or(&DEFAULT_RECORDER)
+}
+
+#[inline]
+pub(crate) fn record_entry(direction: Direction, section: Section, key: &str, value: &str) {
+    recorder().record_entry(direction, section, key.len() + value.len());
+}
+
+#[inline]
+pub(crate) fn record_import(section: Section, key: &str, value: &str, imported: bool) {
+    if imported {
+        record_entry(Direction::Import, section, key, value);
+    } else {
+        recorder().record_filtered(Direction::Import, section);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, sync::Arc, thread};
+
+    use super::*;
+    use crate::{Backward, Forward, MetaInfo};
+
+    thread_local! {
+        static CAPTURING: Cell<bool> = const { Cell::new(false) };
+        static CAPTURED: InMemoryRecorder = const { InMemoryRecorder::new() };
+    }
+
+    /// A global recorder forwarding the events of the capturing threads to a recorder local to
+    /// the thread, since the global recorder is shared by all the tests.
+    struct CaptureRecorder;
+
+    impl Recorder for CaptureRecorder {
+        fn record_entry(&self, direction: Direction, section: Section, bytes: usize) {
+            if CAPTURING.get() {
+                CAPTURED.with(|r| r.record_entry(direction, section, bytes));
+            }
+        }
+
+        fn record_filtered(&self, direction: Direction, section: Section) {
+            if CAPTURING.get() {
+                CAPTURED.with(|r| r.record_filtered(direction, section));
+            }
+        }
+
+        fn record_lookup_depth(&self, depth: usize) {
+            if CAPTURING.get() {
+                CAPTURED.with(|r| r.record_lookup_depth(depth));
+            }
+        }
+    }
+
+    /// Run `f` on a new thread and get the metrics it recorded.
+    fn capture(f: impl FnOnce() + Send) -> MetricsSnapshot {
+        static RECORDER: CaptureRecorder = CaptureRecorder;
+        let _ = set_recorder(&RECORDER);
+        thread::scope(|s| {
+            s.spawn(|| {
+                CAPTURING.set(true);
+                f();
+                CAPTURED.with(InMemoryRecorder::snapshot)
+            })
+            .join()
+            .unwrap()
+        })
+    }
+
+    #[test]
+    fn test_in_memory_recorder() {
+        let recorder = InMemoryRecorder::new();
+        recorder.record_entry(Direction::Export, Section::Persistent, 10);
+        recorder.record_entry(Direction::Export, Section::Persistent, 5);
+        recorder.record_filtered(Direction::Import, Section::Transient);
+        recorder.record_lookup_depth(2);
+        recorder.record_lookup_depth(0);
+
+        let snapshot = recorder.snapshot();
+        assert_eq!(
+            snapshot.get(Direction::Export, Section::Persistent),
+            SectionStats {
+                keys: 2,
+                bytes: 15,
+                filtered: 0
+            }
+        );
+        assert_eq!(
+            snapshot.get(Direction::Import, Section::Transient).filtered,
+            1
+        );
+        assert_eq!(snapshot.lookups, 2);
+        assert_eq!(snapshot.lookup_depth_max, 2);
+        assert_eq!(
+            snapshot.to_string(),
+            "export persistent: keys=2 bytes=15 filtered=0\n\
+             import transient: keys=0 bytes=0 filtered=1\n\
+             lookups=2 depth_sum=2 depth_max=2"
+        );
+    }
+
+    #[test]
+    fn test_lookup_shards() {
+        let recorder = InMemoryRecorder::new();
+        thread::scope(|s| {
+            for depth in 0..4 {
+                let recorder = &recorder;
+                s.spawn(move || recorder.record_lookup_depth(depth));
+            }
+        });
+        let snapshot = recorder.snapshot();
+        assert_eq!(snapshot.lookups, 4);
+        assert_eq!(snapshot.lookup_depth_sum, 6);
+        assert_eq!(snapshot.lookup_depth_max, 3);
+    }
+
+    #[test]
+    fn test_recorded_events() {
+        let snapshot = capture(|| {
+            let mut mi = MetaInfo::new();
+            mi.set_persistent("METRICS_KEY", "value");
+            mi.set_backward_transient("METRICS_KEY", "value");
+            assert_eq!(
+                mi.iter_persistents_and_transients_with_rpc_prefix().count(),
+                1
+            );
+            assert_eq!(mi.iter_backward_transients_with_http_prefix().count(), 1);
+            mi.strip_rpc_prefix_and_set_upstream("RPC_TRANSIT_METRICS_KEY", "value");
+            mi.strip_rpc_prefix_and_set_upstream("OTHER_KEY", "value");
+        });
+
+        assert_eq!(
+            snapshot.get(Direction::Export, Section::Persistent),
+            SectionStats {
+                keys: 1,
+                bytes: "RPC_PERSIST_METRICS_KEYvalue".len() as u64,
+                filtered: 0
+            }
+        );
+        assert_eq!(
+            snapshot.get(Direction::Export, Section::BackwardTransient),
+            SectionStats {
+                keys: 1,
+                // backward transients are exported with the transient prefix
+                bytes: "rpc-transit-metrics-keyvalue".len() as u64,
+                filtered: 0
+            }
+        );
+        assert_eq!(
+            snapshot.get(Direction::Import, Section::Upstream),
+            SectionStats {
+                keys: 1,
+                bytes: "RPC_TRANSIT_METRICS_KEYvalue".len() as u64,
+                filtered: 1
+            }
+        );
+
+        let mut mi = MetaInfo::new();
+        mi.insert_string("k".into(), "v".into());
+        let child = MetaInfo::from(Arc::new(mi));
+        let grandchild = MetaInfo::from(Arc::new(child));
+        let snapshot = capture(|| {
+            assert!(grandchild.get_string("k").is_some());
+            assert!(grandchild.get_string("other").is_none());
+        });
+        assert_eq!(snapshot.lookups, 2);
+        assert_eq!(snapshot.lookup_depth_sum, 4);
+        assert_eq!(snapshot.lookup_depth_max, 2);
+    }
+
+    #[test]
+    fn test_facade_recorder() {
+        use std::{collections::BTreeMap, sync::Mutex};
+
+        use ::metrics::{
+            Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata,
+            SharedString, Unit,
+        };
+
+        /// The values recorded for each metric, keyed by name and labels.
+        #[derive(Default)]
+        struct Values(Mutex<BTreeMap<String, Vec<f64>>>);
+
+        struct Handle(Arc<Values>, String);
+
+        impl CounterFn for Handle {
+            fn increment(&self, value: u64) {
+                self.record(value as f64);
+            }
+
+            fn absolute(&self, _: u64) {}
+        }
+
+        impl HistogramFn for Handle {
+            fn record(&self, value: f64) {
+                let mut values = self.0 .0.lock().unwrap();
+                values.entry(self.1.clone()).or_default().push(value);
+            }
+        }
+
+        struct TestRecorder(Arc<Values>);
+
+        impl TestRecorder {
+            fn handle(&self, key: &Key) -> Arc<Handle> {
+                let labels: Vec<_> = key
+                    .labels()
+                    .map(|l| format!("{}={}", l.key(), l.value()))
+                    .collect();
+                let id = format!("{}{{{}}}", key.name(), labels.join(","));
+                Arc::new(Handle(self.0.clone(), id))
+            }
+        }
+
+        impl ::metrics::Recorder for TestRecorder {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::from_arc(self.handle(key))
+            }
+
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+
+            fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::from_arc(self.handle(key))
+            }
+        }
+
+        let values = Arc::new(Values::default());
+        ::metrics::with_local_recorder(&TestRecorder(values.clone()), || {
+            FacadeRecorder.record_entry(Direction::Export, Section::Persistent, 10);
+            FacadeRecorder.record_filtered(Direction::Import, Section::Upstream);
+            FacadeRecorder.record_lookup_depth(2);
+        });
+        let values = values.0.lock().unwrap();
+        assert_eq!(
+            values.iter().collect::<Vec<_>>(),
+            [
+                (
+                    &"metainfo_entries_total{direction=export,section=persistent}".to_owned(),
+                    &vec![1.0]
+                ),
+                (
+                    &"metainfo_entry_bytes_total{direction=export,section=persistent}".to_owned(),
+                    &vec![10.0]
+                ),
+                (
+                    &"metainfo_filtered_total{direction=import,section=upstream}".to_owned(),
+                    &vec![1.0]
+                ),
+                (&"metainfo_lookup_depth{}".to_owned(), &vec![2.0]),
+            ]
+        );
+    }
+
+    #[cfg(feature = "tonic")]
+    #[test]
+    fn test_grpc_recorded_once() {
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        metadata.insert("rpc-persist-tenant", "t1".parse().unwrap());
+        metadata.insert("x-other", "o".parse().unwrap());
+        let snapshot = capture(|| crate::grpc::extract(&metadata, &mut MetaInfo::new()));
+        assert_eq!(
+            snapshot.get(Direction::Import, Section::Persistent),
+            SectionStats {
+                keys: 1,
+                bytes: "rpc-persist-tenantt1".len() as u64,
+                filtered: 0
+            }
+        );
+        assert_eq!(
+            snapshot.get(Direction::Import, Section::Upstream),
+            SectionStats {
+                keys: 0,
+                bytes: 0,
+                filtered: 1
+            }
+        );
+    }
+}
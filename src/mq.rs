@@ -0,0 +1,274 @@
+//! Propagation of [`MetaInfo`] through message queue headers.
+//!
+//! Producers [`inject`] the forward persistents and transients into the headers of a message with
+//! the rpc prefix, and consumers [`extract`] them like servers do, that is, transients are set as
+//! upstreams. This is the [`RpcPropagator`] over the byte-valued headers of a [`HeaderCarrier`].
+//!
+//! `MetaInfo` values are strings, so headers with non UTF-8 values can't be extracted, [`extract`]
+//! returns the number of such headers with the rpc prefix.
+//!
+//! Examples:
+//! ```rust
+//! use metainfo::{mq, Forward, MetaInfo};
+//!
+//! let mut producer = MetaInfo::new();
+//! producer.set_persistent("TENANT", "t1");
+//!
+//! let mut headers: Vec<(String, Vec<u8>)> = Vec::new();
+//! mq::inject(&producer, &mut headers);
+//!
+//! let mut consumer = MetaInfo::new();
+//! assert_eq!(mq::extract(&headers, &mut consumer), 0);
+//! assert_eq!(consumer.get_persistent("TENANT").unwrap(), "t1");
+//! ```
+
+use std::str;
+
+use faststr::FastStr;
+
+use crate::{
+    convert::{Converter, RpcConverter},
+    propagation::extract_entry,
+    Carrier, MetaInfo, Propagator, RpcPropagator,
+};
+
+/// Byte-valued headers of a message.
+pub trait HeaderCarrier {
+    /// Get the value of the given header.
+    fn get(&self, key: &str) -> Option<&[u8]>;
+    /// Set the value of the given header.
+    fn set(&mut self, key: &str, value: &[u8]);
+    /// Iterate over the header keys.
+    fn keys(&self) -> impl Iterator<Item = &str>;
+}
+
+impl HeaderCarrier for Vec<(String, Vec<u8>)> {
+    #[inline]
+    fn get(&self, key: &str) -> Option<&[u8]> {
+        self.iter()
+            .rfind(|(k, _)| k == key)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    #[inline]
+    fn set(&mut self, key: &str, value: &[u8]) {
+        match self.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => {
+                v.clear();
+                v.extend_from_slice(value);
+            }
+            None => self.push((key.to_string(), value.to_vec())),
+        }
+    }
+
+    #[inline]
+    fn keys(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(k, _)| k.as_str())
+    }
+}
+
+/// NATS headers may have several values, `get` returns the first one. NATS header values are
+/// strings, `set` replaces invalid UTF-8 sequences.
+#[cfg(feature = "async-nats")]
+impl HeaderCarrier for async_nats::HeaderMap {
+    #[inline]
+    fn get(&self, key: &str) -> Option<&[u8]> {
+        async_nats::HeaderMap::get(self, key).map(|v| v.as_str().as_bytes())
+    }
+
+    #[inline]
+    fn set(&mut self, key: &str, value: &[u8]) {
+        self.insert(key, String::from_utf8_lossy(value).as_ref());
+    }
+
+    #[inline]
+    fn keys(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(k, _)| k.as_ref())
+    }
+}
+
+/// Kafka headers are append-only, `set` appends a new header and `get` returns the last one.
+#[cfg(feature = "rdkafka")]
+impl HeaderCarrier for rdkafka::message::OwnedHeaders {
+    #[inline]
+    fn get(&self, key: &str) -> Option<&[u8]> {
+        use rdkafka::message::Headers;
+
+        Headers::iter(self)
+            .filter(|h| h.key == key)
+            .last()
+            .and_then(|h| h.value)
+    }
+
+    #[inline]
+    fn set(&mut self, key: &str, value: &[u8]) {
+        *self = std::mem::take(self).insert(rdkafka::message::Header {
+            key,
+            value: Some(value),
+        });
+    }
+
+    #[inline]
+    fn keys(&self) -> impl Iterator<Item = &str> {
+        use rdkafka::message::Headers;
+
+        Headers::iter(self).map(|h| h.key)
+    }
+}
+
+// the string view of a `HeaderCarrier` used for injection
+struct Headers<'a, C>(&'a mut C);
+
+impl<C: HeaderCarrier> Carrier for Headers<'_, C> {
+    #[inline]
+    fn get_header(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| str::from_utf8(v).ok())
+    }
+
+    #[inline]
+    fn set_header(&mut self, key: FastStr, value: FastStr) {
+        self.0.set(&key, value.as_bytes());
+    }
+
+    #[inline]
+    fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.keys().filter_map(|k| Some((k, self.get_header(k)?)))
+    }
+}
+
+/// Insert the forward persistents and transients of a `MetaInfo` into message headers with the
+/// rpc prefix.
+#[inline]
+pub fn inject<C: HeaderCarrier>(mi: &MetaInfo, carrier: &mut C) {
+    RpcPropagator.inject(mi, &mut Headers(carrier));
+}
+
+/// Extract the forward persistents and transients from message headers into a `MetaInfo`.
+///
+/// Transients are set as upstreams. Returns the number of headers with the rpc prefix which are
+/// skipped since their values are not UTF-8.
+#[inline]
+#[track_caller]
+pub fn extract<C: HeaderCarrier>(carrier: &C, mi: &mut MetaInfo) -> usize {
+    let mut skipped = 0;
+    for key in carrier.keys() {
+        let Some(value) = carrier.get(key) else {
+            continue;
+        };
+        match str::from_utf8(value) {
+            Ok(value) => extract_entry(&RpcPropagator, mi, key, FastStr::new(value)),
+            Err(_) => {
+                let prefixed = RpcConverter.remove_persistent_prefix(key).is_some()
+                    || RpcConverter.remove_transient_prefix(key).is_some();
+                skipped += prefixed as usize;
+            }
+        }
+    }
+    skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Forward;
+
+    #[test]
+    fn test_vec_carrier() {
+        let mut headers: Vec<(String, Vec<u8>)> = vec![("other".into(), b"\xff".to_vec())];
+        headers.set("key", b"v1");
+        headers.set("key", b"v2");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(HeaderCarrier::get(&headers, "key"), Some(&b"v2"[..]));
+        assert_eq!(HeaderCarrier::get(&headers, "other"), Some(&b"\xff"[..]));
+        assert_eq!(headers.keys().collect::<Vec<_>>(), ["other", "key"]);
+    }
+
+    #[test]
+    fn test_inject_extract() {
+        let mut producer = MetaInfo::new();
+        producer.set_persistent("TENANT", "t1");
+        producer.set_transient("REQUEST_ID", "r1");
+
+        let mut headers: Vec<(String, Vec<u8>)> = vec![
+            ("RPC_PERSIST_BAD".into(), vec![0xff]),
+            ("RPC_TRANSIT_BAD".into(), vec![0xff]),
+            ("other".into(), vec![0xff]),
+        ];
+        inject(&producer, &mut headers);
+        assert_eq!(
+            HeaderCarrier::get(&headers, "RPC_PERSIST_TENANT"),
+            Some(&b"t1"[..])
+        );
+        assert_eq!(
+            HeaderCarrier::get(&headers, "RPC_TRANSIT_REQUEST_ID"),
+            Some(&b"r1"[..])
+        );
+
+        let mut consumer = MetaInfo::new();
+        // only the non UTF-8 headers with the rpc prefix are reported
+        assert_eq!(extract(&headers, &mut consumer), 2);
+        assert_eq!(consumer.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(consumer.get_upstream("REQUEST_ID").unwrap(), "r1");
+        assert!(consumer.get_persistent("BAD").is_none());
+    }
+
+    #[cfg(feature = "async-nats")]
+    #[test]
+    fn test_nats_carrier() {
+        let mut producer = MetaInfo::new();
+        producer.set_persistent("TENANT", "t1");
+
+        let mut headers = async_nats::HeaderMap::new();
+        inject(&producer, &mut headers);
+        assert_eq!(
+            HeaderCarrier::get(&headers, "RPC_PERSIST_TENANT"),
+            Some(&b"t1"[..])
+        );
+
+        let mut consumer = MetaInfo::new();
+        assert_eq!(extract(&headers, &mut consumer), 0);
+        assert_eq!(consumer.get_persistent("TENANT").unwrap(), "t1");
+    }
+
+    #[cfg(feature = "rdkafka")]
+    #[test]
+    fn test_kafka_carrier() {
+        use rdkafka::message::{Header, OwnedHeaders};
+
+        let mut producer = MetaInfo::new();
+        producer.set_persistent("TENANT", "t1");
+
+        let mut headers = OwnedHeaders::new().insert(Header {
+            key: "RPC_TRANSIT_USER",
+            value: Some("u1"),
+        });
+        inject(&producer, &mut headers);
+        // duplicate keys resolve to the last value
+        headers = headers.insert(Header {
+            key: "RPC_TRANSIT_USER",
+            value: Some("u2"),
+        });
+        headers = headers.insert(Header {
+            key: "RPC_PERSIST_BAD",
+            value: Some(&[0xffu8][..]),
+        });
+        assert_eq!(
+            HeaderCarrier::get(&headers, "RPC_PERSIST_TENANT"),
+            Some(&b"t1"[..])
+        );
+        assert_eq!(
+            HeaderCarrier::get(&headers, "RPC_TRANSIT_USER"),
+            Some(&b"u2"[..])
+        );
+        assert_eq!(
+            HeaderCarrier::get(&headers, "RPC_PERSIST_BAD"),
+            Some(&[0xffu8][..])
+        );
+
+        let mut consumer = MetaInfo::new();
+        assert_eq!(extract(&headers, &mut consumer), 1);
+        assert_eq!(consumer.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(consumer.get_upstream("USER").unwrap(), "u2");
+        assert!(consumer.get_persistent("BAD").is_none());
+    }
+}
@@ -0,0 +1,273 @@
+//! Observers notified of the mutations of [`MetaInfo`].
+//!
+//! Observers are registered with [`MetaInfo::add_observer`], usually on the root `MetaInfo` of a
+//! request, and are inherited by the `MetaInfo`s derived from it. They are called before each
+//! insert or remove, and can veto it to enforce invariants.
+//!
+//! `extend` and `extend_with` notify each incoming entry as an insert, `clear` is not observed.
+//!
+//! Examples:
+//! ```rust
+//! use metainfo::{Forward, MetaInfo, Mutation, MutationKind, Observer, Section};
+//!
+//! /// The tenant may only be set once per request.
+//! struct TenantOnce;
+//!
+//! impl Observer for TenantOnce {
+//!     fn on_mutation(&self, mi: &MetaInfo, m: &Mutation<'_>) -> bool {
+//!         !(m.section == Section::Persistent
+//!             && m.key == "TENANT"
+//!             && mi.get_persistent("TENANT").is_some())
+//!     }
+//! }
+//!
+//! let mut mi = MetaInfo::new();
+//! mi.add_observer(TenantOnce);
+//! mi.set_persistent("TENANT", "t1");
+//!
+//! let (_, mut child) = mi.derive();
+//! child.set_persistent("TENANT", "t2");
+//! assert_eq!(child.get_persistent("TENANT").unwrap(), "t1");
+//! ```
+
+use std::{panic::Location, sync::Arc};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+
+use crate::{kv::Node, type_map::TypeNames, MetaInfo, Section};
+
+/// The kind of a [`Mutation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutationKind {
+    Insert,
+    Remove,
+}
+
+/// A mutation about to be applied to a [`MetaInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mutation<'a> {
+    pub kind: MutationKind,
+    pub section: Section,
+    /// The key of the entry, or the type name for the typed and faststr sections.
+    ///
+    /// Type names are only known with the `type_names` feature, otherwise the `Debug` output of
+    /// the `TypeId` is given.
+    pub key: &'a str,
+    /// The inserted value, `None` for removes and typed entries.
+    pub value: Option<&'a str>,
+}
+
+/// An observer of the mutations of [`MetaInfo`].
+pub trait Observer: Send + Sync + 'static {
+    /// Called before the mutation is applied to `mi`, return `false` to veto it.
+    ///
+    /// Vetoed inserts are dropped, and vetoed removes return `None`.
+    fn on_mutation(&self, mi: &MetaInfo, mutation: &Mutation<'_>) -> bool;
+}
+
+impl<F> Observer for F
+where
+    F: Fn(&MetaInfo, &Mutation<'_>) -> bool + Send + Sync + 'static,
+{
+    #[inline]
+    fn on_mutation(&self, mi: &MetaInfo, mutation: &Mutation<'_>) -> bool {
+        self(mi, mutation)
+    }
+}
+
+pub(crate) type Observers = Arc<Vec<Arc<dyn Observer>>>;
+
+impl MetaInfo {
+    /// Register an observer on this `MetaInfo` and the `MetaInfo`s derived from it afterwards.
+    pub fn add_observer<O: Observer>(&mut self, observer: O) {
+        Arc::make_mut(self.observers.get_or_insert_with(Default::default)).push(Arc::new(observer));
+    }
+
+    /// Notify the observers and the audit trail, returns `false` if any observer vetoes the
+    /// mutation.
+    #[inline]
+    #[track_caller]
+    pub(crate) fn observe(
+        &self,
+        kind: MutationKind,
+        section: Section,
+        key: &str,
+        value: Option<&str>,
+    ) -> bool {
+        self.observe_at(kind, section, key, value, Location::caller())
+    }
+
+    /// Same as [`MetaInfo::observe`], with the location of the mutation given explicitly, e.g.
+    /// from a closure.
+    #[inline]
+    pub(crate) fn observe_at(
+        &self,
+        kind: MutationKind,
+        section: Section,
+        key: &str,
+        value: Option<&str>,
+        location: &'static Location<'static>,
+    ) -> bool {
+        let mut allowed = true;
+        if let Some(observers) = self.observers.as_ref() {
+            let mutation = Mutation {
+                kind,
+                section,
+                key,
+                value,
+            };
+            // every observer is notified even if one vetoes, so recorders see all the attempts
+            for o in observers.iter() {
+                allowed &= o.on_mutation(self, &mutation);
+            }
+        }
+        self.audit(kind, section, key, allowed, location);
+        allowed
+    }
+
+    /// Same as [`MetaInfo::observe_at`] for an entry of the typed or faststr section, whose key is
+    /// the name of `T`, only computed if there is anything to notify.
+    #[inline]
+    pub(crate) fn observe_type<T: 'static>(
+        &self,
+        kind: MutationKind,
+        section: Section,
+        value: Option<&str>,
+        location: &'static Location<'static>,
+    ) -> bool {
+        if self.observers.is_none() && self.audit.is_none() {
+            return true;
+        }
+        let name = TypeNames::name_of::<T>();
+        self.observe_at(kind, section, &name, value, location)
+    }
+
+    /// Notify the entries of `other` about to be merged into self as inserts, and drop the
+    /// vetoed ones.
+    #[track_caller]
+    pub(crate) fn observe_incoming(&self, other: &mut MetaInfo) {
+        if self.observers.is_none() && self.audit.is_none() {
+            return;
+        }
+        let location = Location::caller();
+        let insert = MutationKind::Insert;
+        if let Some(tmap) = other.tmap.as_mut() {
+            tmap.retain(|_, name| self.observe_at(insert, Section::Typed, name, None, location));
+        }
+        if let Some(faststr_tmap) = other.faststr_tmap.as_mut() {
+            faststr_tmap.retain(|_, name, v| {
+                self.observe_at(insert, Section::FastStr, name, Some(v), location)
+            });
+        }
+        self.observe_map(Section::String, other.smap.as_mut(), location);
+        self.observe_node(Section::FORWARD, other.forward_node.as_mut(), location);
+        self.observe_node(Section::BACKWARD, other.backward_node.as_mut(), location);
+    }
+
+    fn observe_node(
+        &self,
+        sections: [Option<Section>; 3],
+        node: Option<&mut Node>,
+        location: &'static Location<'static>,
+    ) {
+        let Some(node) = node else {
+            return;
+        };
+        for (section, map) in sections.into_iter().zip(node.sections_mut()) {
+            if let Some(section) = section {
+                self.observe_map(section, map.as_mut(), location);
+            }
+        }
+    }
+
+    fn observe_map(
+        &self,
+        section: Section,
+        map: Option<&mut AHashMap<FastStr, FastStr>>,
+        location: &'static Location<'static>,
+    ) {
+        if let Some(map) = map {
+            map.retain(|k, v| self.observe_at(MutationKind::Insert, section, k, Some(v), location));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{type_map::TypeNames, Backward, Forward};
+
+    #[test]
+    fn test_record() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let recorder = {
+            let log = log.clone();
+            move |_: &MetaInfo, m: &Mutation<'_>| {
+                log.lock().unwrap().push(format!(
+                    "{:?} {} {} {:?}",
+                    m.kind, m.section, m.key, m.value
+                ));
+                true
+            }
+        };
+
+        let mut mi = MetaInfo::new();
+        mi.add_observer(recorder);
+        mi.insert(1u8);
+        mi.insert_string("k".into(), "v".into());
+        let (_, mut child) = mi.derive();
+        child.set_persistent("P", "1");
+        child.del_backward_transient("B");
+        child.remove::<u8>();
+
+        let u8_name = TypeNames::name_of::<u8>();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                format!("Insert typed {u8_name} None"),
+                "Insert strings k Some(\"v\")".to_string(),
+                "Insert persistent P Some(\"1\")".to_string(),
+                "Remove backward_transient B None".to_string(),
+                format!("Remove typed {u8_name} None"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_veto() {
+        let mut mi = MetaInfo::new();
+        mi.add_observer(|_: &MetaInfo, m: &Mutation<'_>| m.section != Section::BackwardDownstream);
+        mi.add_observer(|_: &MetaInfo, m: &Mutation<'_>| m.kind != MutationKind::Remove);
+
+        mi.set_backward_downstream("K", "V");
+        assert!(mi.get_backward_downstream("K").is_none());
+
+        mi.insert_faststr::<u8>("v".into());
+        mi.set_transient("K", "V");
+        assert!(mi.remove_faststr::<u8>().is_none());
+        assert!(mi.del_transient("K").is_none());
+        assert_eq!(mi.get_faststr::<u8>().unwrap(), "v");
+        assert_eq!(mi.get_transient("K").unwrap(), "V");
+
+        // observers only apply to the `MetaInfo` they are registered on and its children
+        let mut other = MetaInfo::new();
+        other.set_backward_downstream("K", "V");
+        assert!(other.get_backward_downstream("K").is_some());
+
+        // the incoming entries of `extend` and `extend_with` are observed too
+        other.set_persistent("P", "1");
+        mi.extend(other);
+        assert_eq!(mi.get_persistent("P").unwrap(), "1");
+        assert!(mi.get_backward_downstream("K").is_none());
+
+        let mut other = MetaInfo::new();
+        other.insert(1u32);
+        other.set_backward_downstream("K", "V");
+        mi.extend_with(other, crate::MergeStrategy::Overwrite);
+        assert_eq!(*mi.get::<u32>().unwrap(), 1);
+        assert!(mi.get_backward_downstream("K").is_none());
+    }
+}
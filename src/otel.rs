@@ -0,0 +1,106 @@
+//! Bridge between [`MetaInfo`] and OpenTelemetry.
+//!
+//! OpenTelemetry [`Baggage`] is the counterpart of the forward persistents: both are propagated
+//! through the whole call chain. Entries are converted as is, without any key mapping.
+//!
+//! The OpenTelemetry [`Context`] can also be stored as a typed entry, so it's inherited by derived
+//! `MetaInfo`s and carried by the task local `METAINFO` together with the active span.
+
+use faststr::FastStr;
+use opentelemetry::{
+    baggage::{Baggage, BaggageExt},
+    Context,
+};
+
+use crate::{Forward, MetaInfo};
+
+struct OtelContext(Context);
+
+impl MetaInfo {
+    /// Convert the forward persistents into an OpenTelemetry [`Baggage`].
+    ///
+    /// Note: entries beyond the baggage limits are dropped by OpenTelemetry.
+    pub fn to_baggage(&self) -> Baggage {
+        let mut baggage = Baggage::new();
+        if let Some(persistents) = self.get_all_persistents() {
+            for (k, v) in persistents {
+                baggage.insert(k.to_string(), v.to_string());
+            }
+        }
+        baggage
+    }
+
+    /// Set all entries of an OpenTelemetry [`Baggage`] as forward persistents.
+    #[track_caller]
+    pub fn set_persistents_from_baggage(&mut self, baggage: &Baggage) {
+        for (k, (v, _)) in baggage {
+            self.set_persistent(FastStr::new(k.as_str()), FastStr::new(v.as_str()));
+        }
+    }
+
+    /// Store an OpenTelemetry [`Context`] into this `MetaInfo`.
+    ///
+    /// The baggage of the context is also set as forward persistents.
+    #[track_caller]
+    pub fn set_otel_context(&mut self, cx: Context) {
+        self.set_persistents_from_baggage(cx.baggage());
+        self.insert(OtelContext(cx));
+    }
+
+    /// Get the OpenTelemetry [`Context`] previously stored in this `MetaInfo`.
+    #[inline]
+    pub fn otel_context(&self) -> Option<&Context> {
+        self.get::<OtelContext>().map(|cx| &cx.0)
+    }
+
+    /// Get the stored OpenTelemetry [`Context`], or the current one if not stored, with its
+    /// baggage replaced by the forward persistents.
+    pub fn otel_context_with_baggage(&self) -> Context {
+        let baggage = self.to_baggage();
+        match self.otel_context() {
+            Some(cx) => cx.with_baggage(baggage),
+            None => Context::current_with_baggage(baggage),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::StringValue;
+
+    use super::*;
+
+    #[test]
+    fn test_baggage() {
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TENANT", "t1");
+        let baggage = mi.to_baggage();
+        assert_eq!(baggage.get("TENANT"), Some(&StringValue::from("t1")));
+
+        let mut other = MetaInfo::new();
+        other.set_persistents_from_baggage(&baggage);
+        assert_eq!(other.get_persistent("TENANT").unwrap(), "t1");
+    }
+
+    #[test]
+    fn test_otel_context() {
+        #[derive(Debug, PartialEq)]
+        struct Value(i32);
+
+        let cx = Context::new()
+            .with_value(Value(1))
+            .with_baggage([opentelemetry::KeyValue::new("TENANT", "t1")]);
+        let mut mi = MetaInfo::new();
+        mi.set_otel_context(cx);
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+
+        let (_, mut child) = mi.derive();
+        assert_eq!(child.otel_context().unwrap().get(), Some(&Value(1)));
+
+        child.set_persistent("USER", "u1");
+        let cx = child.otel_context_with_baggage();
+        assert_eq!(cx.get(), Some(&Value(1)));
+        assert_eq!(cx.baggage().get("USER"), Some(&StringValue::from("u1")));
+        assert_eq!(cx.baggage().get("TENANT"), Some(&StringValue::from("t1")));
+    }
+}
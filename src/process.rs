@@ -0,0 +1,232 @@
+//! Propagation of [`MetaInfo`] to child processes through environment variables.
+//!
+//! The parent exports the forward entries with [`MetaInfoCommandExt::with_metainfo`], using the
+//! rpc prefix naming, e.g. `RPC_PERSIST_TENANT`. The child reads them back with
+//! [`MetaInfo::from_parent_env`], where the parent is considered as the upstream: transients are
+//! set as upstreams, see [`EnvTransients::Upstream`].
+//!
+//! Examples:
+//! ```rust,no_run
+//! use std::process::Command;
+//!
+//! use metainfo::{process::MetaInfoCommandExt, Forward, MetaInfo};
+//!
+//! let mut mi = MetaInfo::new();
+//! mi.set_persistent("TENANT", "t1");
+//!
+//! let status = Command::new("helper").with_metainfo(&mi).status();
+//! ```
+//!
+//! `tokio::process::Command` is supported with the `process` feature.
+
+use std::ffi::OsString;
+
+use faststr::FastStr;
+
+use crate::{
+    convert::{Converter, RpcConverter},
+    env::unicode_vars,
+    EnvTransients, Forward, MetaInfo, RPC_PREFIX_PERSISTENT, RPC_PREFIX_TRANSIENT,
+};
+
+/// Extension trait exporting a [`MetaInfo`] into the environment of a command.
+///
+/// Variables with rpc prefix inherited from the current process are removed, so the child only
+/// sees the exported entries. Entries with a nul byte, or a `=` in the key, can't be set as
+/// variables and are skipped.
+pub trait MetaInfoCommandExt {
+    /// Export the forward persistents.
+    fn with_metainfo(&mut self, mi: &MetaInfo) -> &mut Self;
+
+    /// Export the forward persistents and transients, including the remaining timeout.
+    fn with_metainfo_and_transients(&mut self, mi: &MetaInfo) -> &mut Self;
+}
+
+/// The subset of the `Command` api used to export the environment.
+trait EnvCommand {
+    fn set_env(&mut self, key: &str, value: &str);
+    fn remove_env(&mut self, key: &OsString);
+}
+
+impl EnvCommand for std::process::Command {
+    #[inline]
+    fn set_env(&mut self, key: &str, value: &str) {
+        self.env(key, value);
+    }
+
+    #[inline]
+    fn remove_env(&mut self, key: &OsString) {
+        self.env_remove(key);
+    }
+}
+
+#[cfg(feature = "process")]
+impl EnvCommand for tokio::process::Command {
+    #[inline]
+    fn set_env(&mut self, key: &str, value: &str) {
+        self.env(key, value);
+    }
+
+    #[inline]
+    fn remove_env(&mut self, key: &OsString) {
+        self.env_remove(key);
+    }
+}
+
+fn export<C: EnvCommand>(cmd: &mut C, mi: &MetaInfo, transients: bool) {
+    for (k, _) in std::env::vars_os() {
+        let inherited = k.to_str().is_some_and(|k| {
+            k.starts_with(RPC_PREFIX_PERSISTENT) || k.starts_with(RPC_PREFIX_TRANSIENT)
+        });
+        if inherited {
+            cmd.remove_env(&k);
+        }
+    }
+
+    let mut set_env = |k: &str, v: &str| {
+        if exportable(k, v) {
+            cmd.set_env(k, v);
+        }
+    };
+    if transients {
+        for (k, v) in mi.iter_forward_with_deadline(RpcConverter) {
+            set_env(&k, &v);
+        }
+    } else if let Some(persistents) = mi.get_all_persistents() {
+        for (k, v) in persistents {
+            set_env(&RpcConverter.add_persistent_prefix(k), v);
+        }
+    }
+}
+
+// Nul bytes would make the spawn fail, and a `=` in the name would be read back as part of the
+// value.
+#[inline]
+fn exportable(key: &str, value: &str) -> bool {
+    !key.contains(['=', '\0']) && !value.contains('\0')
+}
+
+macro_rules! command_ext_impl {
+    ($ty:ty) => {
+        impl MetaInfoCommandExt for $ty {
+            #[inline]
+            fn with_metainfo(&mut self, mi: &MetaInfo) -> &mut Self {
+                export(self, mi, false);
+                self
+            }
+
+            #[inline]
+            fn with_metainfo_and_transients(&mut self, mi: &MetaInfo) -> &mut Self {
+                export(self, mi, true);
+                self
+            }
+        }
+    };
+}
+
+command_ext_impl!(std::process::Command);
+#[cfg(feature = "process")]
+command_ext_impl!(tokio::process::Command);
+
+impl MetaInfo {
+    /// Creates a `MetaInfo` from the environment exported by the parent process with
+    /// [`MetaInfoCommandExt`].
+    ///
+    /// Transients are set as upstreams, and `RPC_TRANSIT_METAINFO_DEADLINE_MS` sets the deadline.
+    /// Variables with non unicode names or values are skipped.
+    #[inline]
+    pub fn from_parent_env() -> MetaInfo {
+        Self::from_parent_env_vars(unicode_vars())
+    }
+
+    /// Creates a `MetaInfo` from k-v pairs exported by the parent process, see
+    /// [`MetaInfo::from_parent_env`].
+    #[inline]
+    pub fn from_parent_env_vars<I, K, V>(vars: I) -> MetaInfo
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<FastStr>,
+    {
+        Self::from_env_vars_with(vars, EnvTransients::Upstream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, process::Command, time::Duration};
+
+    use super::*;
+
+    fn envs(cmd: &Command) -> Vec<(String, String)> {
+        let mut envs: Vec<_> = cmd
+            .get_envs()
+            .filter_map(|(k, v)| {
+                Some((
+                    k.to_str()?.to_owned(),
+                    v.and_then(OsStr::to_str)?.to_owned(),
+                ))
+            })
+            .collect();
+        envs.sort();
+        envs
+    }
+
+    #[test]
+    fn test_with_metainfo() {
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TENANT", "t1");
+        mi.set_transient("USER", "u1");
+
+        let mut cmd = Command::new("helper");
+        cmd.with_metainfo(&mi);
+        assert_eq!(
+            envs(&cmd),
+            [("RPC_PERSIST_TENANT".to_owned(), "t1".to_owned())]
+        );
+
+        let mut cmd = Command::new("helper");
+        cmd.with_metainfo_and_transients(&mi);
+        assert_eq!(
+            envs(&cmd),
+            [
+                ("RPC_PERSIST_TENANT".to_owned(), "t1".to_owned()),
+                ("RPC_TRANSIT_USER".to_owned(), "u1".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_invalid() {
+        let mut mi = MetaInfo::new();
+        mi.set_persistent("TENANT", "t1");
+        mi.set_persistent("A=B", "v");
+        mi.set_persistent("NUL\0", "v");
+        mi.set_transient("USER", "u\0");
+        mi.set_transient("QUERY", "a=b");
+
+        let mut cmd = Command::new("helper");
+        cmd.with_metainfo_and_transients(&mi);
+        assert_eq!(
+            envs(&cmd),
+            [
+                ("RPC_PERSIST_TENANT".to_owned(), "t1".to_owned()),
+                ("RPC_TRANSIT_QUERY".to_owned(), "a=b".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_parent_env_vars() {
+        let mi = MetaInfo::from_parent_env_vars([
+            ("RPC_PERSIST_TENANT", "t1"),
+            ("RPC_TRANSIT_USER", "u1"),
+            ("RPC_TRANSIT_METAINFO_DEADLINE_MS", "10000"),
+            ("HOME", "/root"),
+        ]);
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(mi.get_upstream("USER").unwrap(), "u1");
+        assert!(mi.get_transient("USER").is_none());
+        assert!(mi.remaining().unwrap() > Duration::from_secs(9));
+    }
+}
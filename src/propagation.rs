@@ -0,0 +1,529 @@
+//! Transport-agnostic propagation of [`MetaInfo`].
+//!
+//! A [`Carrier`] is anything holding string headers, and a [`Propagator`] knows how to inject a
+//! `MetaInfo` into a carrier and extract it back. [`RpcPropagator`] and [`HttpPropagator`]
+//! implement the rpc and http prefix schemes, which are also used by the `*_with_rpc_prefix` and
+//! `*_with_http_prefix` methods of [`Forward`](crate::Forward) and [`Backward`].
+//!
+//! Examples:
+//! ```rust
+//! use std::collections::HashMap;
+//!
+//! use metainfo::{Forward, HttpPropagator, MetaInfo, Propagator};
+//!
+//! let mut client = MetaInfo::new();
+//! client.set_persistent("TENANT", "t1");
+//!
+//! let mut headers: HashMap<String, String> = HashMap::new();
+//! HttpPropagator.inject(&client, &mut headers);
+//! assert_eq!(headers["rpc-persist-tenant"], "t1");
+//!
+//! let server = HttpPropagator.extract(&headers);
+//! assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+//! ```
+
+use std::{collections::HashMap, hash::BuildHasher};
+
+use ahash::AHashMap;
+use faststr::FastStr;
+
+use crate::{
+    convert::{Converter, EscapedHttpConverter, HttpConverter, RpcConverter},
+    Backward, Forward, MetaInfo, DEADLINE_SKEW_MARGIN, DEADLINE_TIMEOUT_KEY, HOP_COUNT_KEY,
+};
+#[cfg(feature = "metrics")]
+use crate::{
+    metrics::{self, Direction},
+    Section,
+};
+
+/// String headers of a transport.
+pub trait Carrier {
+    /// Get the value of the given header.
+    fn get_header(&self, key: &str) -> Option<&str>;
+    /// Set the value of the given header.
+    fn set_header(&mut self, key: FastStr, value: FastStr);
+    /// Iterate over the headers.
+    fn headers(&self) -> impl Iterator<Item = (&str, &str)>;
+}
+
+impl Carrier for AHashMap<FastStr, FastStr> {
+    #[inline]
+    fn get_header(&self, key: &str) -> Option<&str> {
+        self.get(key).map(|v| v.as_str())
+    }
+
+    #[inline]
+    fn set_header(&mut self, key: FastStr, value: FastStr) {
+        self.insert(key, value);
+    }
+
+    #[inline]
+    fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl<S: BuildHasher> Carrier for HashMap<String, String, S> {
+    #[inline]
+    fn get_header(&self, key: &str) -> Option<&str> {
+        self.get(key).map(|v| v.as_str())
+    }
+
+    #[inline]
+    fn set_header(&mut self, key: FastStr, value: FastStr) {
+        self.insert(key.to_string(), value.to_string());
+    }
+
+    #[inline]
+    fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// Injects a [`MetaInfo`] into a [`Carrier`] and extracts it back.
+///
+/// Forward persistents and transients are injected by clients and extracted by servers, where
+/// transients are set as upstreams. Backward transients are injected by servers and extracted by
+/// clients as downstreams.
+pub trait Propagator {
+    /// Insert the forward persistents and transients of a `MetaInfo` into the carrier.
+    fn inject<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C);
+
+    /// Insert the backward transients of a `MetaInfo` into the carrier.
+    fn inject_backward<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C);
+
+    /// Set the entry as a forward persistent if the key is a persistent key of this scheme.
+    ///
+    /// Returns `false` if the key is not a persistent key.
+    #[track_caller]
+    fn extract_persistent(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool;
+
+    /// Set the entry as a forward upstream if the key is a transient key of this scheme.
+    ///
+    /// Returns `false` if the key is not a transient key.
+    #[track_caller]
+    fn extract_upstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool;
+
+    /// Set the entry as a backward downstream if the key is a backward key of this scheme.
+    ///
+    /// Returns `false` if the key is not a backward key.
+    #[track_caller]
+    fn extract_backward_downstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool;
+
+    /// Extract the forward persistents and transients from the carrier into a new `MetaInfo`.
+    #[inline]
+    #[track_caller]
+    fn extract<C: Carrier>(&self, carrier: &C) -> MetaInfo {
+        let mut mi = MetaInfo::new();
+        self.extract_into(carrier, &mut mi);
+        mi
+    }
+
+    /// Extract the forward persistents and transients from the carrier into a `MetaInfo`.
+    #[inline]
+    #[track_caller]
+    fn extract_into<C: Carrier>(&self, carrier: &C, mi: &mut MetaInfo) {
+        for (k, v) in carrier.headers() {
+            extract_entry(self, mi, k, FastStr::new(v));
+        }
+    }
+
+    /// Extract the backward transients from the carrier into a `MetaInfo`.
+    #[inline]
+    #[track_caller]
+    fn extract_backward_into<C: Carrier>(&self, carrier: &C, mi: &mut MetaInfo) {
+        for (k, v) in carrier.headers() {
+            extract_backward_entry(self, mi, k, FastStr::new(v));
+        }
+    }
+}
+
+/// Extract a forward entry as a persistent or an upstream, recording the import once.
+///
+/// This is for the carriers which can't implement [`Carrier`], e.g. because their values have to
+/// be decoded.
+#[inline]
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+#[track_caller]
+pub(crate) fn extract_entry<P: Propagator + ?Sized>(
+    propagator: &P,
+    mi: &mut MetaInfo,
+    key: &str,
+    value: FastStr,
+) {
+    if propagator.extract_persistent(mi, key, value.clone()) {
+        #[cfg(feature = "metrics")]
+        metrics::record_import(Section::Persistent, key, &value, true);
+    } else {
+        let imported = propagator.extract_upstream(mi, key, value.clone());
+        #[cfg(feature = "metrics")]
+        metrics::record_import(Section::Upstream, key, &value, imported);
+    }
+}
+
+/// Extract a backward entry as a downstream, recording the import.
+#[inline]
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+#[track_caller]
+pub(crate) fn extract_backward_entry<P: Propagator + ?Sized>(
+    propagator: &P,
+    mi: &mut MetaInfo,
+    key: &str,
+    value: FastStr,
+) {
+    let imported = propagator.extract_backward_downstream(mi, key, value.clone());
+    #[cfg(feature = "metrics")]
+    metrics::record_import(Section::BackwardDownstream, key, &value, imported);
+}
+
+impl MetaInfo {
+    /// Set a forward persistent received from the upstream, with the prefix already removed.
+    ///
+    /// This is what the built-in [`Propagator`]s do on extraction, custom propagators should call
+    /// it too: the hop count ([`HOP_COUNT_KEY`]) is incremented by one.
+    #[inline]
+    #[track_caller]
+    pub fn import_persistent<V: Into<FastStr>>(&mut self, key: FastStr, value: V) {
+        let value = value.into();
+        if key == HOP_COUNT_KEY {
+            if let Ok(hops) = value.parse::<u32>() {
+                return self.set_persistent(key, hops.saturating_add(1).to_string());
+            }
+        }
+        self.set_persistent(key, value);
+    }
+
+    /// Set a forward transient received from the upstream as an upstream, with the prefix already
+    /// removed.
+    ///
+    /// This is what the built-in [`Propagator`]s do on extraction, custom propagators should call
+    /// it too: the remaining timeout ([`DEADLINE_TIMEOUT_KEY`]) sets the deadline instead, unless
+    /// it's not a number of milliseconds.
+    #[inline]
+    #[track_caller]
+    pub fn import_upstream<V: Into<FastStr>>(&mut self, key: FastStr, value: V) {
+        let value = value.into();
+        if key != DEADLINE_TIMEOUT_KEY
+            || !self.set_deadline_from_timeout_ms(&value, DEADLINE_SKEW_MARGIN)
+        {
+            self.set_upstream(key, value);
+        }
+    }
+}
+
+#[inline]
+fn inject_with<Conv, C>(converter: Conv, mi: &MetaInfo, carrier: &mut C)
+where
+    Conv: Converter + Copy + 'static,
+    C: Carrier,
+{
+    for (k, v) in mi.iter_forward_with_deadline(converter) {
+        carrier.set_header(k, v);
+    }
+}
+
+#[inline]
+fn inject_backward_with<Conv: Converter, C: Carrier>(
+    converter: Conv,
+    mi: &MetaInfo,
+    carrier: &mut C,
+) {
+    if let Some(transients) = mi.get_all_backward_transients() {
+        for (k, v) in transients {
+            let k = converter.add_backward_prefix(k);
+            #[cfg(feature = "metrics")]
+            metrics::record_entry(Direction::Export, Section::BackwardTransient, &k, v);
+            carrier.set_header(k, v.clone());
+        }
+    }
+}
+
+macro_rules! propagator_impl {
+    ($name:ident, $converter:ident) => {
+        impl Propagator for $name {
+            #[inline]
+            fn inject<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C) {
+                inject_with($converter, mi, carrier)
+            }
+
+            #[inline]
+            fn inject_backward<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C) {
+                inject_backward_with($converter, mi, carrier)
+            }
+
+            #[inline]
+            #[track_caller]
+            fn extract_persistent(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+                match $converter.remove_persistent_prefix(key) {
+                    Some(key) => {
+                        mi.import_persistent(key, value);
+                        true
+                    }
+                    None => false,
+                }
+            }
+
+            #[inline]
+            #[track_caller]
+            fn extract_upstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+                match $converter.remove_transient_prefix(key) {
+                    Some(key) => {
+                        mi.import_upstream(key, value);
+                        true
+                    }
+                    None => false,
+                }
+            }
+
+            #[inline]
+            #[track_caller]
+            fn extract_backward_downstream(
+                &self,
+                mi: &mut MetaInfo,
+                key: &str,
+                value: FastStr,
+            ) -> bool {
+                match $converter.remove_backward_prefix(key) {
+                    Some(key) => {
+                        mi.set_backward_downstream(key, value);
+                        true
+                    }
+                    None => false,
+                }
+            }
+        }
+    };
+}
+
+/// The rpc prefix scheme, e.g. `RPC_PERSIST_TEST_KEY`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RpcPropagator;
+
+/// The http prefix scheme, e.g. `rpc-persist-test-key`.
+///
+/// The prefixes are matched ignoring ascii case on extraction, since proxies may normalize the
+/// header names, e.g. to `Rpc-Persist-Test-Key`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HttpPropagator;
+
+/// The http prefix scheme with lossless key escaping, e.g. `rpc-persist-t.r.a.c.ei.d` for
+/// `TraceId`.
+///
+/// Keys made of uppercase letters, digits and `_` are converted as by [`HttpPropagator`], so both
+/// sides can be migrated independently as long as other keys are not used. Other chars are
+/// escaped with chars which are valid in header names and survive case normalization:
+/// - a lowercase letter is written as `.` followed by the letter;
+/// - any other byte is written as `~` followed by two hex digits, e.g. `~2d` for `-`.
+///
+/// Examples:
+/// ```rust
+/// use std::collections::HashMap;
+///
+/// use metainfo::{EscapedHttpPropagator, Forward, MetaInfo, Propagator};
+///
+/// let mut client = MetaInfo::new();
+/// client.set_persistent("TraceId", "t1");
+/// client.set_persistent("user-id", "u1");
+///
+/// let mut headers: HashMap<String, String> = HashMap::new();
+/// EscapedHttpPropagator.inject(&client, &mut headers);
+/// assert_eq!(headers["rpc-persist-t.r.a.c.ei.d"], "t1");
+///
+/// let server = EscapedHttpPropagator.extract(&headers);
+/// assert_eq!(server.get_persistent("TraceId").unwrap(), "t1");
+/// assert_eq!(server.get_persistent("user-id").unwrap(), "u1");
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EscapedHttpPropagator;
+
+propagator_impl!(RpcPropagator, RpcConverter);
+propagator_impl!(HttpPropagator, HttpConverter);
+propagator_impl!(EscapedHttpPropagator, EscapedHttpConverter);
+
+/// A propagator injecting with a primary scheme and extracting with both the primary and the
+/// fallback schemes, e.g. to accept the rpc and http prefixes from the same carrier.
+///
+/// Keys are tried with the primary scheme first.
+///
+/// Examples:
+/// ```rust
+/// use std::collections::HashMap;
+///
+/// use metainfo::{FallbackPropagator, Forward, HttpPropagator, Propagator, RpcPropagator};
+///
+/// let mut headers: HashMap<String, String> = HashMap::new();
+/// headers.insert("rpc-persist-tenant".into(), "t1".into());
+/// headers.insert("RPC_TRANSIT_USER".into(), "u1".into());
+///
+/// let server = FallbackPropagator::new(HttpPropagator, RpcPropagator).extract(&headers);
+/// assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+/// assert_eq!(server.get_upstream("USER").unwrap(), "u1");
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FallbackPropagator<P, F> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P: Propagator, F: Propagator> FallbackPropagator<P, F> {
+    /// Creates a `FallbackPropagator` from the primary and fallback schemes.
+    #[inline]
+    pub fn new(primary: P, fallback: F) -> Self {
+        FallbackPropagator { primary, fallback }
+    }
+}
+
+impl<P: Propagator, F: Propagator> Propagator for FallbackPropagator<P, F> {
+    #[inline]
+    fn inject<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C) {
+        self.primary.inject(mi, carrier)
+    }
+
+    #[inline]
+    fn inject_backward<C: Carrier>(&self, mi: &MetaInfo, carrier: &mut C) {
+        self.primary.inject_backward(mi, carrier)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn extract_persistent(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+        self.primary.extract_persistent(mi, key, value.clone())
+            || self.fallback.extract_persistent(mi, key, value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn extract_upstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+        self.primary.extract_upstream(mi, key, value.clone())
+            || self.fallback.extract_upstream(mi, key, value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn extract_backward_downstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+        self.primary
+            .extract_backward_downstream(mi, key, value.clone())
+            || self.fallback.extract_backward_downstream(mi, key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Forward;
+
+    #[test]
+    fn test_rpc_propagator() {
+        let mut client = MetaInfo::new();
+        client.set_persistent("TEST_KEY", "persist");
+        client.set_transient("TEST_KEY", "transit");
+
+        let mut carrier = AHashMap::new();
+        RpcPropagator.inject(&client, &mut carrier);
+        assert_eq!(carrier.len(), 2);
+        assert_eq!(carrier["RPC_PERSIST_TEST_KEY"], "persist");
+        assert_eq!(carrier["RPC_TRANSIT_TEST_KEY"], "transit");
+
+        let server = RpcPropagator.extract(&carrier);
+        assert_eq!(server.get_persistent("TEST_KEY").unwrap(), "persist");
+        assert_eq!(server.get_upstream("TEST_KEY").unwrap(), "transit");
+        assert!(server.get_transient("TEST_KEY").is_none());
+    }
+
+    #[test]
+    fn test_http_propagator_ignore_case() {
+        let mut carrier: HashMap<String, String> = HashMap::new();
+        carrier.insert("Rpc-Persist-Tenant".into(), "t1".into());
+        carrier.insert("RPC-TRANSIT-USER-ID".into(), "u1".into());
+        carrier.insert("Rpc-Backward-Cost".into(), "10".into());
+
+        let server = HttpPropagator.extract(&carrier);
+        assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(server.get_upstream("USER_ID").unwrap(), "u1");
+
+        let mut client = MetaInfo::new();
+        HttpPropagator.extract_backward_into(&carrier, &mut client);
+        assert_eq!(client.get_backward_downstream("COST").unwrap(), "10");
+    }
+
+    #[test]
+    fn test_fallback_propagator() {
+        let propagator = FallbackPropagator::new(HttpPropagator, RpcPropagator);
+        let mut carrier: HashMap<String, String> = HashMap::new();
+        carrier.insert("rpc-persist-tenant".into(), "t1".into());
+        carrier.insert("RPC_PERSIST_REGION".into(), "r1".into());
+        carrier.insert("RPC_TRANSIT_USER".into(), "u1".into());
+        carrier.insert("RPC_BACKWARD_COST".into(), "10".into());
+        carrier.insert("x-other".into(), "o".into());
+
+        let server = propagator.extract(&carrier);
+        assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(server.get_persistent("REGION").unwrap(), "r1");
+        assert_eq!(server.get_upstream("USER").unwrap(), "u1");
+        assert_eq!(server.get_all_persistents().unwrap().len(), 2);
+        assert_eq!(server.get_all_upstreams().unwrap().len(), 1);
+
+        let mut client = MetaInfo::new();
+        propagator.extract_backward_into(&carrier, &mut client);
+        assert_eq!(client.get_backward_downstream("COST").unwrap(), "10");
+
+        // injected with the primary scheme only
+        let mut out: HashMap<String, String> = HashMap::new();
+        propagator.inject(&server, &mut out);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out["rpc-persist-region"], "r1");
+    }
+
+    #[test]
+    fn test_custom_propagator() {
+        struct Custom;
+
+        impl Propagator for Custom {
+            fn inject<C: Carrier>(&self, _: &MetaInfo, _: &mut C) {}
+
+            fn inject_backward<C: Carrier>(&self, _: &MetaInfo, _: &mut C) {}
+
+            fn extract_persistent(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+                key.strip_prefix("x-p-")
+                    .map(|key| mi.import_persistent(FastStr::new(key), value))
+                    .is_some()
+            }
+
+            fn extract_upstream(&self, mi: &mut MetaInfo, key: &str, value: FastStr) -> bool {
+                key.strip_prefix("x-t-")
+                    .map(|key| mi.import_upstream(FastStr::new(key), value))
+                    .is_some()
+            }
+
+            fn extract_backward_downstream(&self, _: &mut MetaInfo, _: &str, _: FastStr) -> bool {
+                false
+            }
+        }
+
+        let mut carrier: HashMap<String, String> = HashMap::new();
+        carrier.insert(format!("x-p-{HOP_COUNT_KEY}"), "1".into());
+        carrier.insert(format!("x-t-{DEADLINE_TIMEOUT_KEY}"), "1000".into());
+        let server = Custom.extract(&carrier);
+        assert_eq!(server.hop_count(), Some(2));
+        assert!(server.deadline().is_some());
+        assert!(server.get_all_upstreams().is_none());
+    }
+
+    #[test]
+    fn test_http_propagator_backward() {
+        let mut server = MetaInfo::new();
+        server.set_backward_transient("TEST_KEY", "backward");
+
+        let mut carrier: HashMap<String, String> = HashMap::new();
+        HttpPropagator.inject_backward(&server, &mut carrier);
+        assert_eq!(carrier["rpc-backward-test-key"], "backward");
+
+        let mut client = MetaInfo::new();
+        HttpPropagator.extract_backward_into(&carrier, &mut client);
+        assert_eq!(
+            client.get_backward_downstream("TEST_KEY").unwrap(),
+            "backward"
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! RAII guards for temporary modifications of a [`MetaInfo`].
+//!
+//! A guard applies a modification when created and restores the previous state of the current
+//! scope (the previous value, or its absence) when dropped.
+//!
+//! The modification is notified to the [observers](crate::Observer) as an insert, and nothing is
+//! restored if it's vetoed. The restoration undoes an allowed modification, so it's not observed.
+//!
+//! Examples:
+//! ```rust
+//! use metainfo::{Forward, MetaInfo};
+//!
+//! let mut mi = MetaInfo::new();
+//! mi.insert::<i8>(1);
+//! {
+//!     let mut guard = mi.scoped_insert::<i8>(2);
+//!     assert_eq!(*guard.get::<i8>().unwrap(), 2);
+//!
+//!     let guard = guard.scoped_set_transient("TEST_KEY", "value");
+//!     assert_eq!(guard.get_transient("TEST_KEY").unwrap(), "value");
+//! }
+//! assert_eq!(*mi.get::<i8>().unwrap(), 1);
+//! assert!(mi.get_transient("TEST_KEY").is_none());
+//! ```
+
+use std::{
+    ops::{Deref, DerefMut},
+    panic::Location,
+};
+
+use faststr::FastStr;
+
+use crate::{MetaInfo, MutationKind, Section, TypeMap, DEFAULT_MAP_SIZE};
+
+/// Restores a previous state of a [`MetaInfo`].
+///
+/// This is implemented by the restore states held by the guards in this module.
+pub trait Restore {
+    fn restore(self, mi: &mut MetaInfo);
+}
+
+/// The previous typed value of `T` in the current scope.
+pub struct Typed<T: Send + Sync + 'static> {
+    // `None` if the insert was vetoed
+    prev: Option<Option<T>>,
+}
+
+impl<T: Send + Sync + 'static> Typed<T> {
+    #[inline]
+    fn apply(mi: &mut MetaInfo, val: T, location: &'static Location<'static>) -> Self {
+        if !mi.observe_type::<T>(MutationKind::Insert, Section::Typed, None, location) {
+            return Typed { prev: None };
+        }
+        let tmap = mi
+            .tmap
+            .get_or_insert_with(|| TypeMap::with_capacity(DEFAULT_MAP_SIZE));
+        let prev = tmap.remove::<T>();
+        tmap.insert(val);
+        Typed { prev: Some(prev) }
+    }
+}
+
+impl<T: Send + Sync + 'static> Restore for Typed<T> {
+    #[inline]
+    fn restore(self, mi: &mut MetaInfo) {
+        let (Some(prev), Some(tmap)) = (self.prev, mi.tmap.as_mut()) else {
+            return;
+        };
+        match prev {
+            Some(prev) => tmap.insert(prev),
+            None => drop(tmap.remove::<T>()),
+        }
+    }
+}
+
+/// The previous transient value of a key in the current scope.
+pub struct Transient {
+    key: FastStr,
+    // `None` if the insert was vetoed
+    prev: Option<Option<FastStr>>,
+}
+
+impl Transient {
+    #[inline]
+    fn apply(
+        mi: &mut MetaInfo,
+        key: FastStr,
+        value: FastStr,
+        location: &'static Location<'static>,
+    ) -> Self {
+        let kind = MutationKind::Insert;
+        if !mi.observe_at(kind, Section::Transient, &key, Some(&value), location) {
+            return Transient { key, prev: None };
+        }
+        mi.ensure_forward_node();
+        let node = mi.forward_node.as_mut().unwrap();
+        let prev = node.del_transient(&key);
+        node.set_transient(key.clone(), value);
+        Transient {
+            key,
+            prev: Some(prev),
+        }
+    }
+}
+
+impl Restore for Transient {
+    #[inline]
+    fn restore(self, mi: &mut MetaInfo) {
+        let (Some(prev), Some(node)) = (self.prev, mi.forward_node.as_mut()) else {
+            return;
+        };
+        match prev {
+            Some(prev) => node.set_transient(self.key, prev),
+            None => drop(node.del_transient(&self.key)),
+        }
+    }
+}
+
+/// A guard borrowing a [`MetaInfo`] which restores the previous state on drop.
+///
+/// The guard dereferences to the borrowed `MetaInfo`, so it can still be used while the guard is
+/// alive.
+pub struct ScopedGuard<'a, R: Restore> {
+    mi: &'a mut MetaInfo,
+    restore: Option<R>,
+}
+
+impl<R: Restore> Deref for ScopedGuard<'_, R> {
+    type Target = MetaInfo;
+
+    #[inline]
+    fn deref(&self) -> &MetaInfo {
+        self.mi
+    }
+}
+
+impl<R: Restore> DerefMut for ScopedGuard<'_, R> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut MetaInfo {
+        self.mi
+    }
+}
+
+impl<R: Restore> Drop for ScopedGuard<'_, R> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(restore) = self.restore.take() {
+            restore.restore(self.mi);
+        }
+    }
+}
+
+impl MetaInfo {
+    /// Insert a type into this `MetaInfo` until the returned guard is dropped.
+    ///
+    /// The previous value of `T` in the current scope, if any, is restored on drop.
+    #[inline]
+    #[track_caller]
+    pub fn scoped_insert<T: Send + Sync + 'static>(&mut self, val: T) -> ScopedGuard<'_, Typed<T>> {
+        let restore = Typed::apply(self, val, Location::caller());
+        ScopedGuard {
+            mi: self,
+            restore: Some(restore),
+        }
+    }
+
+    /// Set a transient k-v into this `MetaInfo` until the returned guard is dropped.
+    ///
+    /// The previous value of the key in the current scope, if any, is restored on drop.
+    #[inline]
+    #[track_caller]
+    pub fn scoped_set_transient<K: Into<FastStr>, V: Into<FastStr>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> ScopedGuard<'_, Transient> {
+        let restore = Transient::apply(self, key.into(), value.into(), Location::caller());
+        ScopedGuard {
+            mi: self,
+            restore: Some(restore),
+        }
+    }
+}
+
+/// A guard on the task local [`METAINFO`](crate::METAINFO) which restores the previous state on
+/// drop.
+///
+/// Unlike [`ScopedGuard`], this guard doesn't hold a borrow of the `MetaInfo`, so it can be held
+/// across `.await` points. The previous state is restored into the `METAINFO` current when the
+/// guard is dropped, so it should be dropped inside the same `METAINFO` scope it was created in:
+/// inside a nested scope, the previous state is restored into the nested `MetaInfo` instead.
+/// The restoration is skipped outside of a `METAINFO` scope, or while it's borrowed.
+#[cfg(feature = "task_local")]
+pub struct CurrentScopedGuard<R: Restore> {
+    restore: Option<R>,
+}
+
+#[cfg(feature = "task_local")]
+impl<R: Restore> Drop for CurrentScopedGuard<R> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(restore) = self.restore.take() {
+            let _ = crate::METAINFO.try_with(|mi| {
+                if let Ok(mut mi) = mi.try_borrow_mut() {
+                    restore.restore(&mut mi);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(feature = "task_local")]
+impl<R: Restore> CurrentScopedGuard<R> {
+    #[inline]
+    fn apply(f: impl FnOnce(&mut MetaInfo) -> R) -> Option<CurrentScopedGuard<R>> {
+        let restore = crate::METAINFO
+            .try_with(|mi| mi.try_borrow_mut().ok().map(|mut mi| f(&mut mi)))
+            .ok()??;
+        Some(CurrentScopedGuard {
+            restore: Some(restore),
+        })
+    }
+}
+
+/// Insert a type into the task local [`METAINFO`](crate::METAINFO) until the returned guard is
+/// dropped.
+///
+/// Returns `None` if called outside of a `METAINFO` scope, or while it's borrowed.
+#[cfg(feature = "task_local")]
+#[inline]
+#[track_caller]
+pub fn insert_current<T: Send + Sync + 'static>(val: T) -> Option<CurrentScopedGuard<Typed<T>>> {
+    let location = Location::caller();
+    CurrentScopedGuard::apply(|mi| Typed::apply(mi, val, location))
+}
+
+/// Set a transient k-v into the task local [`METAINFO`](crate::METAINFO) until the returned guard
+/// is dropped.
+///
+/// Returns `None` if called outside of a `METAINFO` scope, or while it's borrowed.
+#[cfg(feature = "task_local")]
+#[inline]
+#[track_caller]
+pub fn set_transient_current<K: Into<FastStr>, V: Into<FastStr>>(
+    key: K,
+    value: V,
+) -> Option<CurrentScopedGuard<Transient>> {
+    let location = Location::caller();
+    CurrentScopedGuard::apply(|mi| Transient::apply(mi, key.into(), value.into(), location))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Forward, Mutation};
+
+    #[test]
+    fn test_scoped_insert() {
+        let mut mi = MetaInfo::new();
+        mi.insert::<i8>(1);
+        {
+            let mut guard = mi.scoped_insert::<i8>(2);
+            assert_eq!(*guard.get::<i8>().unwrap(), 2);
+            guard.insert::<i16>(16);
+        }
+        assert_eq!(*mi.get::<i8>().unwrap(), 1);
+        assert_eq!(*mi.get::<i16>().unwrap(), 16);
+
+        drop(mi.scoped_insert::<u8>(8));
+        assert!(mi.get::<u8>().is_none());
+
+        // only the current scope is restored
+        let (_, mut child) = mi.derive();
+        {
+            let guard = child.scoped_insert::<i8>(3);
+            assert_eq!(*guard.get::<i8>().unwrap(), 3);
+        }
+        assert_eq!(*child.get::<i8>().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_scoped_set_transient() {
+        let mut mi = MetaInfo::new();
+        mi.set_transient("KEY", "old");
+        {
+            let guard = mi.scoped_set_transient("KEY", "new");
+            assert_eq!(guard.get_transient("KEY").unwrap(), "new");
+        }
+        assert_eq!(mi.get_transient("KEY").unwrap(), "old");
+
+        drop(mi.scoped_set_transient("OTHER", "value"));
+        assert!(mi.get_transient("OTHER").is_none());
+    }
+
+    #[test]
+    fn test_scoped_vetoed() {
+        let mut mi = MetaInfo::new();
+        mi.insert::<i8>(1);
+        mi.set_transient("KEY", "old");
+        // removes are vetoed, the guards must not lose the previous values
+        mi.add_observer(|_: &MetaInfo, m: &Mutation<'_>| m.kind != MutationKind::Remove);
+        {
+            let mut guard = mi.scoped_insert::<i8>(2);
+            assert_eq!(*guard.get::<i8>().unwrap(), 2);
+            let guard = guard.scoped_set_transient("KEY", "new");
+            assert_eq!(guard.get_transient("KEY").unwrap(), "new");
+        }
+        assert_eq!(*mi.get::<i8>().unwrap(), 1);
+        assert_eq!(mi.get_transient("KEY").unwrap(), "old");
+
+        // vetoed inserts are not applied, and nothing is restored
+        mi.add_observer(|_: &MetaInfo, m: &Mutation<'_>| m.section != Section::Transient);
+        {
+            let guard = mi.scoped_set_transient("KEY", "new");
+            assert_eq!(guard.get_transient("KEY").unwrap(), "old");
+        }
+        assert_eq!(mi.get_transient("KEY").unwrap(), "old");
+    }
+
+    #[cfg(feature = "task_local")]
+    #[test]
+    fn test_scoped_current() {
+        use std::cell::RefCell;
+
+        use crate::METAINFO;
+
+        assert!(insert_current(1i8).is_none());
+
+        let mut mi = MetaInfo::new();
+        mi.insert::<i8>(1);
+        METAINFO.sync_scope(RefCell::new(mi), || {
+            {
+                let _typed = insert_current(2i8).unwrap();
+                let _transient = set_transient_current("KEY", "value").unwrap();
+                METAINFO.with(|mi| {
+                    let mi = mi.borrow();
+                    assert_eq!(*mi.get::<i8>().unwrap(), 2);
+                    assert_eq!(mi.get_transient("KEY").unwrap(), "value");
+                });
+            }
+            METAINFO.with(|mi| {
+                let mi = mi.borrow();
+                assert_eq!(*mi.get::<i8>().unwrap(), 1);
+                assert!(mi.get_transient("KEY").is_none());
+            });
+        });
+    }
+
+    #[cfg(feature = "task_local")]
+    #[test]
+    fn test_scoped_current_borrowed() {
+        use std::cell::RefCell;
+
+        use crate::METAINFO;
+
+        let mut mi = MetaInfo::new();
+        mi.set_transient("KEY", "old");
+        METAINFO.sync_scope(RefCell::new(mi), || {
+            METAINFO.with(|mi| {
+                let _borrow = mi.borrow();
+                assert!(set_transient_current("KEY", "new").is_none());
+            });
+
+            // dropping the guard while borrowed doesn't panic, the restoration is skipped
+            let guard = set_transient_current("KEY", "new").unwrap();
+            METAINFO.with(|mi| {
+                let _borrow = mi.borrow();
+                drop(guard);
+            });
+            METAINFO.with(|mi| assert_eq!(mi.borrow().get_transient("KEY").unwrap(), "new"));
+        });
+    }
+
+    #[cfg(feature = "task_local")]
+    #[test]
+    fn test_scoped_current_nested() {
+        use std::cell::RefCell;
+
+        use crate::METAINFO;
+
+        let mut mi = MetaInfo::new();
+        mi.set_transient("KEY", "outer");
+        METAINFO.sync_scope(RefCell::new(mi), || {
+            let guard = set_transient_current("KEY", "new").unwrap();
+            // dropped inside a nested scope, the previous state is restored into the nested one
+            let mut inner = MetaInfo::new();
+            inner.set_transient("KEY", "inner");
+            let inner = METAINFO.sync_scope(RefCell::new(inner), || {
+                drop(guard);
+                METAINFO.with(|mi| mi.borrow().get_transient("KEY"))
+            });
+            assert_eq!(inner.unwrap(), "outer");
+            METAINFO.with(|mi| assert_eq!(mi.borrow().get_transient("KEY").unwrap(), "new"));
+        });
+    }
+
+    #[cfg(feature = "task_local")]
+    #[test]
+    fn test_scoped_current_across_await() {
+        use std::cell::RefCell;
+
+        use crate::METAINFO;
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut mi = MetaInfo::new();
+        mi.set_transient("KEY", "old");
+        rt.block_on(METAINFO.scope(RefCell::new(mi), async {
+            {
+                let _guard = set_transient_current("KEY", "new").unwrap();
+                tokio::task::yield_now().await;
+                METAINFO.with(|mi| assert_eq!(mi.borrow().get_transient("KEY").unwrap(), "new"));
+            }
+            METAINFO.with(|mi| assert_eq!(mi.borrow().get_transient("KEY").unwrap(), "old"));
+        }));
+    }
+}
@@ -0,0 +1,17 @@
+//! Helpers shared by the unit tests.
+
+/// A xorshift pseudo random number generator with a fixed seed, so failures are reproducible.
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    pub(crate) fn new() -> Self {
+        XorShift(0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
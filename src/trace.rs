@@ -0,0 +1,289 @@
+//! Integration with `tracing`.
+//!
+//! [`MetaInfoLayer`] snapshots the configured keys of the task local
+//! [`METAINFO`](crate::METAINFO) when a span is created, and stores them as
+//! [`MetaInfoFields`](crate::MetaInfoFields) in the span extensions. Nothing is recorded if the
+//! task local is mutably borrowed when the span is created.
+//!
+//! The keys are not span fields, since `tracing` only records the fields declared by the span.
+//! To print them with `tracing_subscriber::fmt`, wrap its event formatter with
+//! [`MetaInfoLayer::format_event`], which prints the keys in front of each event, e.g.
+//! `[TENANT=*** REQUEST_ID=r1] INFO app: message`. Other formatters or layers can read them from
+//! the extensions of the spans in scope with `span.extensions().get::<MetaInfoFields>()`.
+//!
+//! Examples:
+//! ```rust
+//! use metainfo::MetaInfoLayer;
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! let layer = MetaInfoLayer::new()
+//!     .persistent("TENANT")
+//!     .transient("REQUEST_ID")
+//!     .redact(|key, value| {
+//!         if key == "TENANT" {
+//!             "***".into()
+//!         } else {
+//!             value.clone()
+//!         }
+//!     });
+//! let fmt = tracing_subscriber::fmt::layer()
+//!     .event_format(layer.format_event(tracing_subscriber::fmt::format()));
+//! let subscriber = tracing_subscriber::registry().with(layer).with(fmt);
+//! ```
+
+use std::fmt;
+
+use faststr::FastStr;
+use tracing::{span, Event, Subscriber};
+use tracing_subscriber::{
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields},
+    layer::Context,
+    registry::LookupSpan,
+    Layer,
+};
+
+use crate::{MetaInfo, MetaInfoFields, MetaInfoSelector};
+
+/// A [`Layer`] recording the configured keys of the task local `METAINFO` into new spans.
+#[derive(Clone, Default)]
+pub struct MetaInfoLayer {
+    selector: MetaInfoSelector,
+}
+
+impl MetaInfoLayer {
+    /// Creates a `MetaInfoLayer` recording nothing.
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record the given forward persistent key.
+    #[inline]
+    pub fn persistent<K: Into<FastStr>>(mut self, key: K) -> Self {
+        self.selector = self.selector.persistent(key);
+        self
+    }
+
+    /// Record the given forward transient key.
+    ///
+    /// The upstream value is recorded if there is no transient value, since transients received
+    /// by a server are stored as upstreams.
+    #[inline]
+    pub fn transient<K: Into<FastStr>>(mut self, key: K) -> Self {
+        self.selector = self.selector.transient(key);
+        self
+    }
+
+    /// Set a hook to redact values before they are recorded.
+    ///
+    /// The hook is called with the key and the original value and returns the value to record.
+    #[inline]
+    pub fn redact<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, &FastStr) -> FastStr + Send + Sync + 'static,
+    {
+        self.selector = self.selector.redact(f);
+        self
+    }
+
+    /// Snapshot the configured keys of the given `MetaInfo`.
+    #[inline]
+    pub fn fields(&self, mi: &MetaInfo) -> MetaInfoFields {
+        self.selector.fields(mi)
+    }
+
+    /// Wrap an event formatter of `tracing_subscriber::fmt` to print the configured keys in front
+    /// of each event.
+    ///
+    /// The keys are snapshotted from the task local `METAINFO` when the event is formatted. Outside
+    /// of a `METAINFO` scope, the keys recorded by this layer in the innermost span are printed.
+    #[inline]
+    pub fn format_event<E>(&self, inner: E) -> MetaInfoFormat<E> {
+        MetaInfoFormat {
+            inner,
+            selector: self.selector.clone(),
+        }
+    }
+}
+
+impl From<MetaInfoSelector> for MetaInfoLayer {
+    #[inline]
+    fn from(selector: MetaInfoSelector) -> Self {
+        MetaInfoLayer { selector }
+    }
+}
+
+impl<S> Layer<S> for MetaInfoLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let fields = self.selector.current();
+        if fields.is_empty() {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().replace(fields);
+        }
+    }
+}
+
+/// An event formatter printing the configured keys in front of the events formatted by `E`, see
+/// [`MetaInfoLayer::format_event`].
+#[derive(Clone)]
+pub struct MetaInfoFormat<E> {
+    inner: E,
+    selector: MetaInfoSelector,
+}
+
+impl<S, N, E> FormatEvent<S, N> for MetaInfoFormat<E>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    E: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut fields = self.selector.current();
+        if fields.is_empty() {
+            let recorded = ctx.event_scope().and_then(|mut scope| {
+                scope.find_map(|span| span.extensions().get::<MetaInfoFields>().cloned())
+            });
+            if let Some(recorded) = recorded {
+                fields = recorded;
+            }
+        }
+        if !fields.is_empty() {
+            write!(writer, "[{fields}] ")?;
+        }
+        self.inner.format_event(ctx, writer, event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{Forward, METAINFO};
+
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl<S> Layer<S> for Collect
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let fields = span
+                .extensions()
+                .get::<MetaInfoFields>()
+                .map(|f| f.to_string())
+                .unwrap_or_default();
+            self.0.lock().unwrap().push(fields);
+        }
+    }
+
+    #[test]
+    fn test_layer() {
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let layer = MetaInfoLayer::new()
+            .persistent("TENANT")
+            .transient("REQUEST_ID")
+            .persistent("MISSING")
+            .redact(|key, value| {
+                if key == "TENANT" {
+                    FastStr::from_static_str("***")
+                } else {
+                    value.clone()
+                }
+            });
+        let subscriber = tracing_subscriber::registry()
+            .with(layer)
+            .with(Collect(collected.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("outside").in_scope(|| {});
+
+            let mut mi = MetaInfo::new();
+            mi.set_persistent("TENANT", "t1");
+            mi.set_upstream("REQUEST_ID", "r1");
+            METAINFO.sync_scope(RefCell::new(mi), || {
+                tracing::info_span!("inside").in_scope(|| {});
+                // the layer must not panic while the task local is mutably borrowed
+                METAINFO.with(|mi| {
+                    let _guard = mi.borrow_mut();
+                    tracing::info_span!("borrowed").in_scope(|| {});
+                });
+            });
+        });
+
+        assert_eq!(
+            *collected.lock().unwrap(),
+            ["", "TENANT=*** REQUEST_ID=r1", ""]
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_format_event() {
+        let output = Output::default();
+        let layer = MetaInfoLayer::new()
+            .persistent("TENANT")
+            .transient("REQUEST_ID");
+        let format = tracing_subscriber::fmt::format()
+            .without_time()
+            .with_target(false);
+        let writer = output.clone();
+        let fmt = tracing_subscriber::fmt::layer()
+            .event_format(layer.format_event(format))
+            .with_writer(move || writer.clone());
+        let subscriber = tracing_subscriber::registry().with(layer).with(fmt);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside");
+
+            let mut mi = MetaInfo::new();
+            mi.set_persistent("TENANT", "t1");
+            mi.set_upstream("REQUEST_ID", "r1");
+            let span = METAINFO.sync_scope(RefCell::new(mi), || {
+                tracing::info!("inside");
+                tracing::info_span!("request")
+            });
+            // the keys recorded in the span are printed outside of the scope
+            span.in_scope(|| tracing::info!("in span"));
+        });
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                " INFO outside",
+                "[TENANT=t1 REQUEST_ID=r1]  INFO inside",
+                "[TENANT=t1 REQUEST_ID=r1]  INFO request: in span",
+            ]
+        );
+    }
+}
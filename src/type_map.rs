@@ -4,10 +4,70 @@ use std::{
     marker::PhantomData,
 };
 
+use faststr::FastStr;
 use rustc_hash::FxHashMapRand;
 
+use crate::heap::{self, HeapSize};
+
 pub(crate) type AnyObject = Box<dyn Any + Send + Sync>;
 
+type HeapSizeFn = fn(&(dyn Any + Send + Sync)) -> usize;
+
+fn heap_size_of<T: HeapSize + 'static>(v: &(dyn Any + Send + Sync)) -> usize {
+    v.downcast_ref::<T>().map_or(0, T::heap_size)
+}
+
+/// The names of the types inserted into a map, used by the json export, the diffs, the merge
+/// reports and the observers.
+///
+/// The names are only kept with the `type_names` feature, otherwise the `TypeId`s are used.
+#[derive(Debug, Default)]
+pub(crate) struct TypeNames {
+    #[cfg(feature = "type_names")]
+    names: FxHashMapRand<TypeId, &'static str>,
+}
+
+impl TypeNames {
+    #[inline]
+    pub(crate) fn record<T: 'static>(&mut self) {
+        #[cfg(feature = "type_names")]
+        self.names
+            .insert(TypeId::of::<T>(), std::any::type_name::<T>());
+    }
+
+    /// Get the name of `T`, as it's reported for the entries of the maps.
+    #[inline]
+    pub(crate) fn name_of<T: 'static>() -> FastStr {
+        #[cfg(feature = "type_names")]
+        return FastStr::from_static_str(std::any::type_name::<T>());
+        #[cfg(not(feature = "type_names"))]
+        FastStr::from_string(format!("{:?}", TypeId::of::<T>()))
+    }
+
+    /// Get the name of the given type.
+    #[inline]
+    pub(crate) fn get(&self, id: &TypeId) -> FastStr {
+        #[cfg(feature = "type_names")]
+        if let Some(name) = self.names.get(id) {
+            return FastStr::from_static_str(name);
+        }
+        FastStr::from_string(format!("{id:?}"))
+    }
+
+    #[inline]
+    #[cfg_attr(not(feature = "type_names"), allow(unused_variables))]
+    pub(crate) fn extend(&mut self, other: TypeNames) {
+        #[cfg(feature = "type_names")]
+        self.names.extend(other.names);
+    }
+
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        #[cfg(feature = "type_names")]
+        self.names.clear();
+    }
+}
+
 pub struct Entry<'a, K: 'a, V: 'a> {
     inner: MapEntry<'a, K, AnyObject>,
     _marker: PhantomData<V>,
@@ -67,6 +127,9 @@ impl<'a, K, V> Entry<'a, K, V> {
 #[derive(Debug, Default)]
 pub struct TypeMap {
     inner: FxHashMapRand<TypeId, AnyObject>,
+    // the heap size functions of the values inserted with `insert_sized`, allocated on first use
+    heap_sizes: Option<Box<FxHashMapRand<TypeId, HeapSizeFn>>>,
+    names: TypeNames,
 }
 
 impl TypeMap {
@@ -74,6 +137,8 @@ impl TypeMap {
     pub fn new() -> Self {
         TypeMap {
             inner: FxHashMapRand::default(),
+            heap_sizes: None,
+            names: TypeNames::default(),
         }
     }
 
@@ -81,11 +146,23 @@ impl TypeMap {
     pub fn with_capacity(capacity: usize) -> Self {
         TypeMap {
             inner: FxHashMapRand::with_capacity_and_hasher(capacity, Default::default()),
+            heap_sizes: None,
+            names: TypeNames::default(),
         }
     }
 
     #[inline]
     pub fn insert<T: Send + Sync + 'static>(&mut self, t: T) {
+        self.names.record::<T>();
+        self.set_heap_size(TypeId::of::<T>(), None);
+        self.inner.insert(TypeId::of::<T>(), Box::new(t));
+    }
+
+    /// Insert a value whose heap allocations are accounted by the approximate heap size.
+    #[inline]
+    pub fn insert_sized<T: HeapSize + Send + Sync + 'static>(&mut self, t: T) {
+        self.names.record::<T>();
+        self.set_heap_size(TypeId::of::<T>(), Some(heap_size_of::<T>));
         self.inner.insert(TypeId::of::<T>(), Box::new(t));
     }
 
@@ -110,6 +187,7 @@ impl TypeMap {
 
     #[inline]
     pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.set_heap_size(TypeId::of::<T>(), None);
         self.inner
             .remove(&TypeId::of::<T>())
             .and_then(|boxed| boxed.downcast().ok().map(|boxed| *boxed))
@@ -118,11 +196,40 @@ impl TypeMap {
     #[inline]
     pub fn clear(&mut self) {
         self.inner.clear();
+        self.heap_sizes = None;
+        self.names.clear();
     }
 
     #[inline]
     pub fn extend(&mut self, other: TypeMap) {
-        self.inner.extend(other.inner)
+        let mut other_heap_sizes = other.heap_sizes;
+        for (id, v) in other.inner {
+            let heap_size = other_heap_sizes.as_mut().and_then(|h| h.remove(&id));
+            self.set_heap_size(id, heap_size);
+            self.inner.insert(id, v);
+        }
+        self.names.extend(other.names);
+    }
+
+    #[inline]
+    pub(crate) fn contains_id(&self, id: &TypeId) -> bool {
+        self.inner.contains_key(id)
+    }
+
+    #[inline]
+    fn set_heap_size(&mut self, id: TypeId, heap_size: Option<HeapSizeFn>) {
+        match heap_size {
+            Some(f) => {
+                self.heap_sizes
+                    .get_or_insert_with(Default::default)
+                    .insert(id, f);
+            }
+            None => {
+                if let Some(heap_sizes) = self.heap_sizes.as_mut() {
+                    heap_sizes.remove(&id);
+                }
+            }
+        }
     }
 
     #[inline]
@@ -132,12 +239,47 @@ impl TypeMap {
 
     #[inline]
     pub fn entry<T: 'static>(&mut self) -> Entry<'_, TypeId, T> {
+        self.names.record::<T>();
         Entry {
             inner: self.inner.entry(TypeId::of::<T>()),
             _marker: PhantomData,
         }
     }
 
+    /// Retain only the entries for which `f` returns true, given the type id and name.
+    #[inline]
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&TypeId, &FastStr) -> bool) {
+        let names = &self.names;
+        self.inner.retain(|id, _| f(id, &names.get(id)))
+    }
+
+    /// Iterate over the type names of the entries.
+    #[inline]
+    pub(crate) fn type_names(&self) -> impl Iterator<Item = FastStr> + '_ {
+        self.inner.keys().map(|id| self.names.get(id))
+    }
+
+    /// Get the approximate size of the heap allocations of the map and its values.
+    pub(crate) fn approx_heap_size(&self) -> usize {
+        let values: usize = self
+            .inner
+            .iter()
+            .map(|(id, v)| {
+                let heap_size = self
+                    .heap_sizes
+                    .as_ref()
+                    .and_then(|h| h.get(id))
+                    .map_or(0, |f| f(v.as_ref()));
+                std::mem::size_of_val(v.as_ref()) + heap_size
+            })
+            .sum();
+        let heap_sizes = self
+            .heap_sizes
+            .as_ref()
+            .map_or(0, |h| std::mem::size_of_val(h.as_ref()) + heap::map_size(h));
+        heap::map_size(&self.inner) + values + heap_sizes
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
@@ -0,0 +1,350 @@
+//! Propagation of [`MetaInfo`] through URL query strings and cookies.
+//!
+//! Browser-originated requests can't always set custom headers, so the forward entries can be
+//! carried as query parameters, e.g. `?meta.persist.tenant=t1&meta.transit.user-id=u1`, or in a
+//! single cookie named [`META_COOKIE_NAME`] whose value is encoded the same way. Keys follow the
+//! http format, e.g. `USER_ID` is carried as `user-id`, and values are percent-encoded.
+//!
+//! Entries are only imported and exported within the given [`Limits`]. Entries are converted with
+//! [`HttpPropagator`], so extracted transients are set as upstreams, the hop count is incremented
+//! and the remaining timeout is carried as `meta.transit.metainfo-deadline-ms`.
+//!
+//! Examples:
+//! ```rust
+//! use metainfo::{web, Forward, MetaInfo};
+//!
+//! let mut mi = MetaInfo::new();
+//! web::extract_query("page=1&meta.persist.tenant=t%201", &mut mi, web::Limits::default());
+//! assert_eq!(mi.get_persistent("TENANT").unwrap(), "t 1");
+//!
+//! let mut query = String::new();
+//! web::inject_query(&mi, &mut query, web::Limits::default());
+//! assert_eq!(query, "meta.persist.tenant=t%201");
+//! ```
+
+use std::fmt::Write;
+
+use faststr::FastStr;
+
+use crate::{
+    Carrier, HttpPropagator, MetaInfo, Propagator, HTTP_PREFIX_PERSISTENT, HTTP_PREFIX_TRANSIENT,
+};
+
+/// The query parameter prefix of forward persistents.
+pub const QUERY_PREFIX_PERSISTENT: &str = "meta.persist.";
+/// The query parameter prefix of forward transients.
+pub const QUERY_PREFIX_TRANSIENT: &str = "meta.transit.";
+/// The name of the cookie carrying the forward entries.
+pub const META_COOKIE_NAME: &str = "rpc-meta";
+
+/// Limits on the entries carried by a query string or a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of entries.
+    pub max_entries: usize,
+    /// The maximum encoded length of all entries, including separators.
+    pub max_len: usize,
+}
+
+impl Default for Limits {
+    /// 32 entries of 2KiB in total, which fits the url length limits of browsers and the 4KiB
+    /// cookie size limit.
+    #[inline]
+    fn default() -> Self {
+        Limits {
+            max_entries: 32,
+            max_len: 2048,
+        }
+    }
+}
+
+#[inline]
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+#[inline]
+fn percent_encode(s: &str, out: &mut String) {
+    for &b in s.as_bytes() {
+        if is_unreserved(b) {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "%{b:02X}");
+        }
+    }
+}
+
+#[inline]
+fn percent_encoded_len(s: &str) -> usize {
+    s.bytes()
+        .map(|b| if is_unreserved(b) { 1 } else { 3 })
+        .sum()
+}
+
+/// Decode a percent-encoded component, `+` is decoded as a space.
+#[inline]
+fn percent_decode(s: &str) -> Option<FastStr> {
+    if !s.bytes().any(|b| b == b'%' || b == b'+') {
+        return Some(FastStr::new(s));
+    }
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hi = (iter.next()? as char).to_digit(16)?;
+                let lo = (iter.next()? as char).to_digit(16)?;
+                bytes.push((hi << 4 | lo) as u8);
+            }
+            b'+' => bytes.push(b' '),
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok().map(FastStr::from_string)
+}
+
+/// Convert a query parameter name to the http format, e.g. `meta.persist.a` to `rpc-persist-a`.
+#[inline]
+fn to_http_key(name: &str) -> Option<String> {
+    let (prefix, key) = if let Some(key) = name.strip_prefix(QUERY_PREFIX_PERSISTENT) {
+        (HTTP_PREFIX_PERSISTENT, key)
+    } else if let Some(key) = name.strip_prefix(QUERY_PREFIX_TRANSIENT) {
+        (HTTP_PREFIX_TRANSIENT, key)
+    } else {
+        return None;
+    };
+    let mut http_key = String::with_capacity(prefix.len() + key.len());
+    http_key.push_str(prefix);
+    http_key.push_str(key);
+    Some(http_key)
+}
+
+/// The decoded meta parameters of a query string, with keys in the http format.
+#[derive(Debug, Default)]
+struct QueryParams(Vec<(FastStr, FastStr)>);
+
+impl Carrier for QueryParams {
+    #[inline]
+    fn get_header(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .rfind(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[inline]
+    fn set_header(&mut self, key: FastStr, value: FastStr) {
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((key, value)),
+        }
+    }
+
+    #[inline]
+    fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// Extract the forward entries from a query string, without the leading `?`, into a
+/// `MetaInfo`.
+///
+/// Parameters without meta prefix, or which can't be decoded, are skipped. Entries beyond the
+/// limits are dropped, returns the number of entries extracted.
+pub fn extract_query(query: &str, mi: &mut MetaInfo, limits: Limits) -> usize {
+    let mut params = QueryParams::default();
+    let mut entries = 0;
+    let mut len = 0;
+    for pair in query.split('&') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let Some(http_key) = percent_decode(name).and_then(|name| to_http_key(&name)) else {
+            continue;
+        };
+        len += pair.len() + (entries > 0) as usize;
+        if entries >= limits.max_entries || len > limits.max_len {
+            break;
+        }
+        let Some(value) = percent_decode(value) else {
+            continue;
+        };
+        params.set_header(FastStr::from_string(http_key), value);
+        entries += 1;
+    }
+    HttpPropagator.extract_into(&params, mi);
+    entries
+}
+
+/// Append the forward persistents and transients of a `MetaInfo` to a query string, e.g. the
+/// query of a redirect location.
+///
+/// Entries beyond the limits are dropped, returns `false` if any entry is dropped.
+pub fn inject_query(mi: &MetaInfo, query: &mut String, limits: Limits) -> bool {
+    let mut entries = 0;
+    let mut len = 0;
+    let mut complete = true;
+    let mut params = QueryParams::default();
+    HttpPropagator.inject(mi, &mut params);
+    for (k, v) in params.0 {
+        let (prefix, key) = if let Some(key) = k.strip_prefix(HTTP_PREFIX_PERSISTENT) {
+            (QUERY_PREFIX_PERSISTENT, key)
+        } else if let Some(key) = k.strip_prefix(HTTP_PREFIX_TRANSIENT) {
+            (QUERY_PREFIX_TRANSIENT, key)
+        } else {
+            continue;
+        };
+        let pair_len = prefix.len() + percent_encoded_len(key) + 1 + percent_encoded_len(&v);
+        let sep_len = (entries > 0) as usize;
+        if entries >= limits.max_entries || len + sep_len + pair_len > limits.max_len {
+            complete = false;
+            continue;
+        }
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(prefix);
+        percent_encode(key, query);
+        query.push('=');
+        percent_encode(&v, query);
+        entries += 1;
+        len += sep_len + pair_len;
+    }
+    complete
+}
+
+/// Extract the forward entries from the [`META_COOKIE_NAME`] cookie of a `Cookie` header into a
+/// `MetaInfo`, see [`extract_query`].
+pub fn extract_cookie(cookie_header: &str, mi: &mut MetaInfo, limits: Limits) -> usize {
+    cookie_header
+        .split(';')
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == META_COOKIE_NAME)
+        .map(|(_, value)| extract_query(value.trim_matches('"'), mi, limits))
+        .unwrap_or(0)
+}
+
+/// Encode the forward persistents and transients of a `MetaInfo` as the value of the
+/// [`META_COOKIE_NAME`] cookie, see [`inject_query`].
+///
+/// Returns `None` if there is no entry to carry.
+pub fn inject_cookie(mi: &MetaInfo, limits: Limits) -> Option<String> {
+    let mut value = String::new();
+    inject_query(mi, &mut value, limits);
+    (!value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Forward;
+
+    #[test]
+    fn test_percent_encoding() {
+        let mut encoded = String::new();
+        percent_encode("a b&c=值", &mut encoded);
+        assert_eq!(encoded, "a%20b%26c%3D%E5%80%BC");
+        assert_eq!(percent_encoded_len("a b&c=值"), encoded.len());
+        assert_eq!(percent_decode(&encoded).unwrap(), "a b&c=值");
+        assert_eq!(percent_decode("a+b").unwrap(), "a b");
+        assert!(percent_decode("%E5%80").is_none());
+        assert!(percent_decode("%G0").is_none());
+        assert!(percent_decode("%2").is_none());
+    }
+
+    #[test]
+    fn test_query() {
+        let mut mi = MetaInfo::new();
+        let n = extract_query(
+            "page=1&meta.persist.tenant=t1&meta.transit.user-id=u%261&meta.persist.bad=%ff&flag",
+            &mut mi,
+            Limits::default(),
+        );
+        assert_eq!(n, 2);
+        assert_eq!(mi.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(mi.get_upstream("USER_ID").unwrap(), "u&1");
+        assert!(mi.get_persistent("BAD").is_none());
+
+        let mut client = MetaInfo::new();
+        client.set_persistent("TENANT", "t1");
+        client.set_transient("USER_ID", "u&1");
+        let mut query = String::from("page=1");
+        assert!(inject_query(&client, &mut query, Limits::default()));
+        assert!(query.starts_with("page=1&meta."));
+        assert!(query.contains("meta.persist.tenant=t1"));
+        assert!(query.contains("meta.transit.user-id=u%261"));
+
+        let mut server = MetaInfo::new();
+        assert_eq!(extract_query(&query, &mut server, Limits::default()), 2);
+        assert_eq!(server.get_persistent("TENANT").unwrap(), "t1");
+        assert_eq!(server.get_upstream("USER_ID").unwrap(), "u&1");
+    }
+
+    #[test]
+    fn test_query_deadline_and_hops() {
+        use std::time::Duration;
+
+        let mut client = MetaInfo::new();
+        client.set_timeout(Duration::from_secs(10));
+        client.start_hop_tracking();
+        let mut query = String::new();
+        assert!(inject_query(&client, &mut query, Limits::default()));
+        assert!(query.contains("meta.transit.metainfo-deadline-ms="));
+
+        let mut server = MetaInfo::new();
+        extract_query(&query, &mut server, Limits::default());
+        assert!(server.remaining().unwrap() > Duration::from_secs(9));
+        assert!(server.get_upstream(crate::DEADLINE_TIMEOUT_KEY).is_none());
+        assert_eq!(server.hop_count(), Some(1));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut client = MetaInfo::new();
+        for i in 0..10 {
+            client.set_persistent(format!("K{i}"), "v");
+        }
+        // each entry is `meta.persist.kN=v`, 17 bytes
+        let limits = Limits {
+            max_entries: 3,
+            max_len: 17 * 4 + 3,
+        };
+        let mut query = String::new();
+        assert!(!inject_query(&client, &mut query, limits));
+        assert_eq!(query.split('&').count(), 3);
+
+        let limits = Limits {
+            max_entries: 10,
+            max_len: 17 * 2 + 1,
+        };
+        query.clear();
+        assert!(!inject_query(&client, &mut query, limits));
+        assert_eq!(query.len(), 17 * 2 + 1);
+
+        let mut all = String::new();
+        assert!(inject_query(&client, &mut all, Limits::default()));
+        let mut server = MetaInfo::new();
+        assert_eq!(extract_query(&all, &mut server, limits), 2);
+        assert_eq!(server.get_all_persistents().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_cookie() {
+        let mut client = MetaInfo::new();
+        client.set_persistent("TENANT", "t 1");
+        let value = inject_cookie(&client, Limits::default()).unwrap();
+        assert_eq!(value, "meta.persist.tenant=t%201");
+        assert!(inject_cookie(&MetaInfo::new(), Limits::default()).is_none());
+
+        let header = format!("session=abc; {META_COOKIE_NAME}={value}; theme=dark");
+        let mut server = MetaInfo::new();
+        assert_eq!(extract_cookie(&header, &mut server, Limits::default()), 1);
+        assert_eq!(server.get_persistent("TENANT").unwrap(), "t 1");
+
+        let mut server = MetaInfo::new();
+        assert_eq!(
+            extract_cookie("session=abc", &mut server, Limits::default()),
+            0
+        );
+    }
+}
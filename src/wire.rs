@@ -0,0 +1,331 @@
+//! Compact binary encoding of the string state of [`MetaInfo`].
+//!
+//! The encoding starts with a version byte, followed by six sections in order: the string map,
+//! the forward persistents, transients and upstreams, and the backward transients and
+//! downstreams. Each section is a varint entry count followed by the entries, and each entry is a
+//! varint key length, the key, a varint value length and the value. Varints are unsigned LEB128.
+//!
+//! The string map is flattened, that is, entries of the parents are encoded too, shadowed by the
+//! entries of their children. Typed values are not encoded.
+
+use std::{error::Error, fmt, ops::Range};
+
+use ahash::AHashMap;
+use bytes::{BufMut, Bytes, BytesMut};
+use faststr::FastStr;
+
+use crate::{Backward, Forward, MetaInfo, DEFAULT_MAP_SIZE};
+
+/// The current version of the binary encoding.
+pub const WIRE_VERSION: u8 = 1;
+
+const MAX_VARINT_LEN: usize = 10;
+
+/// The error returned by [`MetaInfo::decode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The version byte is not supported.
+    UnsupportedVersion(u8),
+    /// The input ends in the middle of a varint or a string.
+    UnexpectedEof,
+    /// A varint is longer than 10 bytes or does not fit in `usize`.
+    VarintOverflow,
+    /// A key or value is not valid UTF-8.
+    InvalidUtf8,
+    /// The input has bytes left after the last section.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported version {v}"),
+            DecodeError::UnexpectedEof => f.write_str("unexpected end of input"),
+            DecodeError::VarintOverflow => f.write_str("varint overflow"),
+            DecodeError::InvalidUtf8 => f.write_str("invalid utf-8 string"),
+            DecodeError::TrailingBytes(n) => write!(f, "{n} trailing bytes"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+#[inline]
+fn put_varint(buf: &mut BytesMut, mut n: usize) {
+    while n >= 0x80 {
+        buf.put_u8(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.put_u8(n as u8);
+}
+
+#[inline]
+fn varint_len(mut n: usize) -> usize {
+    let mut len = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        len += 1;
+    }
+    len
+}
+
+#[inline]
+fn section_len<'a>(entries: impl Iterator<Item = (&'a FastStr, &'a FastStr)>) -> (usize, usize) {
+    entries.fold((0, 0), |(count, len), (k, v)| {
+        (
+            count + 1,
+            len + varint_len(k.len()) + k.len() + varint_len(v.len()) + v.len(),
+        )
+    })
+}
+
+#[inline]
+fn put_section<'a>(
+    buf: &mut BytesMut,
+    count: usize,
+    entries: impl Iterator<Item = (&'a FastStr, &'a FastStr)>,
+) {
+    put_varint(buf, count);
+    for (k, v) in entries {
+        put_varint(buf, k.len());
+        buf.put_slice(k.as_bytes());
+        put_varint(buf, v.len());
+        buf.put_slice(v.as_bytes());
+    }
+}
+
+struct Decoder {
+    buf: Bytes,
+    pos: usize,
+}
+
+impl Decoder {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    #[inline]
+    fn varint(&mut self) -> Result<usize, DecodeError> {
+        let mut n: u64 = 0;
+        for i in 0..MAX_VARINT_LEN {
+            let Some(&b) = self.buf.get(self.pos) else {
+                return Err(DecodeError::UnexpectedEof);
+            };
+            self.pos += 1;
+            let bits = (b & 0x7f) as u64;
+            // the 10th byte can only carry the highest bit of an u64
+            if i == MAX_VARINT_LEN - 1 && bits > 1 {
+                return Err(DecodeError::VarintOverflow);
+            }
+            n |= bits << (7 * i);
+            if b & 0x80 == 0 {
+                return usize::try_from(n).map_err(|_| DecodeError::VarintOverflow);
+            }
+        }
+        Err(DecodeError::VarintOverflow)
+    }
+
+    #[inline]
+    fn string(&mut self) -> Result<FastStr, DecodeError> {
+        let len = self.varint()?;
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let range = Range {
+            start: self.pos,
+            end: self.pos + len,
+        };
+        std::str::from_utf8(&self.buf[range.clone()]).map_err(|_| DecodeError::InvalidUtf8)?;
+        self.pos = range.end;
+        // SAFETY: the bytes are checked to be valid UTF-8 above.
+        Ok(unsafe { FastStr::from_bytes_unchecked(self.buf.slice(range)) })
+    }
+
+    #[inline]
+    fn section(&mut self, mut f: impl FnMut(FastStr, FastStr)) -> Result<usize, DecodeError> {
+        let count = self.varint()?;
+        // each entry takes at least two bytes, reject the count before looping on it
+        if count > self.remaining() / 2 {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        for _ in 0..count {
+            let k = self.string()?;
+            let v = self.string()?;
+            f(k, v);
+        }
+        Ok(count)
+    }
+}
+
+impl MetaInfo {
+    /// Collect the string map of self and all parents, children shadowing their parents.
+    pub(crate) fn flatten_strings(&self) -> AHashMap<FastStr, FastStr> {
+        let mut chain = Vec::new();
+        let mut cur = Some(self);
+        while let Some(mi) = cur {
+            chain.push(mi);
+            cur = mi.parent.as_deref();
+        }
+        let mut strings = AHashMap::new();
+        for mi in chain.into_iter().rev() {
+            if let Some(smap) = mi.smap.as_ref() {
+                strings.extend(smap.iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+        }
+        strings
+    }
+
+    /// Encode the string state of self into `buf`.
+    ///
+    /// See the [module docs](crate::wire) for the format.
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let strings = self.flatten_strings();
+        let empty = AHashMap::new();
+        let sections = [
+            &strings,
+            self.get_all_persistents().unwrap_or(&empty),
+            self.get_all_transients().unwrap_or(&empty),
+            self.get_all_upstreams().unwrap_or(&empty),
+            self.get_all_backward_transients().unwrap_or(&empty),
+            self.get_all_backward_downstreams().unwrap_or(&empty),
+        ];
+        let lens = sections.map(|s| section_len(s.iter()));
+        buf.reserve(
+            1 + lens
+                .iter()
+                .map(|(count, len)| varint_len(*count) + len)
+                .sum::<usize>(),
+        );
+
+        buf.put_u8(WIRE_VERSION);
+        for (section, (count, _)) in sections.iter().zip(lens) {
+            put_section(buf, count, section.iter());
+        }
+    }
+
+    /// Decode a `MetaInfo` encoded by [`MetaInfo::encode_into`].
+    ///
+    /// Keys and values are slices of `buf`, no string is copied.
+    pub fn decode(buf: Bytes) -> Result<MetaInfo, DecodeError> {
+        let mut mi = MetaInfo::new();
+        let Some(&version) = buf.first() else {
+            return Err(DecodeError::UnexpectedEof);
+        };
+        if version != WIRE_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let mut d = Decoder { buf, pos: 1 };
+
+        let mut smap = AHashMap::with_capacity(DEFAULT_MAP_SIZE);
+        if d.section(|k, v| {
+            smap.insert(k, v);
+        })? > 0
+        {
+            mi.smap = Some(smap);
+        }
+        d.section(|k, v| mi.set_persistent(k, v))?;
+        d.section(|k, v| mi.set_transient(k, v))?;
+        d.section(|k, v| mi.set_upstream(k, v))?;
+        d.section(|k, v| mi.set_backward_transient(k, v))?;
+        d.section(|k, v| mi.set_backward_downstream(k, v))?;
+
+        match d.remaining() {
+            0 => Ok(mi),
+            n => Err(DecodeError::TrailingBytes(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::XorShift;
+
+    fn sample() -> MetaInfo {
+        let mut parent = MetaInfo::new();
+        parent.insert_string("shadowed".into(), "parent".into());
+        parent.insert_string("inherited".into(), "parent".into());
+        let mut mi = MetaInfo::from(Arc::new(parent));
+        mi.insert_string("shadowed".into(), "child".into());
+        mi.set_persistent("PERSIST", "p");
+        mi.set_transient("TRANSIT", "t");
+        mi.set_upstream("UPSTREAM", "u");
+        mi.set_backward_transient("BACKWARD", "b");
+        mi.set_backward_downstream("DOWNSTREAM", "d".repeat(200));
+        mi
+    }
+
+    fn encode(mi: &MetaInfo) -> Bytes {
+        let mut buf = BytesMut::new();
+        mi.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let buf = encode(&sample());
+        let mi = MetaInfo::decode(buf.clone()).unwrap();
+        assert_eq!(mi.get_string("shadowed").unwrap(), "child");
+        assert_eq!(mi.get_string("inherited").unwrap(), "parent");
+        assert_eq!(mi.get_persistent("PERSIST").unwrap(), "p");
+        assert_eq!(mi.get_transient("TRANSIT").unwrap(), "t");
+        assert_eq!(mi.get_upstream("UPSTREAM").unwrap(), "u");
+        assert_eq!(mi.get_backward_transient("BACKWARD").unwrap(), "b");
+        let downstream = mi.get_backward_downstream("DOWNSTREAM").unwrap();
+        assert_eq!(downstream, "d".repeat(200));
+        assert!(buf.as_ptr_range().contains(&downstream.as_ptr()));
+    }
+
+    #[test]
+    fn test_empty() {
+        let buf = encode(&MetaInfo::new());
+        assert_eq!(&buf[..], &[WIRE_VERSION, 0, 0, 0, 0, 0, 0]);
+        let mi = MetaInfo::decode(buf).unwrap();
+        assert!(mi.smap.is_none());
+        assert!(mi.get_all_persistents().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let decode = |b: &'static [u8]| MetaInfo::decode(Bytes::from_static(b)).unwrap_err();
+        assert_eq!(decode(b""), DecodeError::UnexpectedEof);
+        assert_eq!(decode(b"\x02"), DecodeError::UnsupportedVersion(2));
+        assert_eq!(decode(b"\x01\x00"), DecodeError::UnexpectedEof);
+        assert_eq!(
+            decode(b"\x01\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f"),
+            DecodeError::VarintOverflow
+        );
+        assert_eq!(
+            decode(b"\x01\x01\x01\xff\x00\x00\x00\x00\x00\x00"),
+            DecodeError::InvalidUtf8
+        );
+        assert_eq!(
+            decode(b"\x01\x00\x00\x00\x00\x00\x00\x00"),
+            DecodeError::TrailingBytes(1)
+        );
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let buf = encode(&sample());
+        for i in 0..buf.len() {
+            assert!(MetaInfo::decode(buf.slice(..i)).is_err());
+        }
+
+        let mut rng = XorShift::new();
+        for _ in 0..10_000 {
+            let mut mutated = buf.to_vec();
+            for _ in 0..rng.next() % 4 + 1 {
+                let i = (rng.next() % mutated.len() as u64) as usize;
+                mutated[i] = rng.next() as u8;
+            }
+            let _ = MetaInfo::decode(Bytes::from(mutated));
+
+            let random: Vec<u8> = (0..rng.next() % 64).map(|_| rng.next() as u8).collect();
+            let _ = MetaInfo::decode(Bytes::from(random));
+        }
+    }
+}